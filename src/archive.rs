@@ -0,0 +1,310 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use flate2::read::GzDecoder;
+use tar::Archive;
+use anyhow::{Context, Result};
+use log::{debug, trace};
+
+// Chart archives come from third parties, so instead of unpacking them completely only the files hmum needs
+// are streamed out of them, within limits similar to the ones helm itself enforces when loading a chart.
+
+const MAX_ENTRIES: usize = 10_000;
+const MAX_FILE_SIZE: u64 = 5 * 1024 * 1024;
+// Of all entries together, uncompressed, whether they are extracted or skipped
+const MAX_ARCHIVE_SIZE: u64 = 100 * 1024 * 1024;
+
+/// Extracts the files of a chart archive for which `is_wanted` returns true into `extraction_path` and
+/// returns the path of the extracted chart dir. `is_wanted` gets paths relative to the chart dir, like
+/// `values.yaml` or `charts/redis/values.yaml`.
+///
+/// Charts are packaged with a single top-level dir that is usually named after the chart, but not always,
+/// so the chart dir is the top-level dir named `chart_name` if it has a Chart.yaml, or else the only top-level dir
+/// with a Chart.yaml.
+pub fn extract_chart_files<F>(archive_path: &Path, extraction_path: &Path, chart_name: &str, is_wanted: F) -> Result<PathBuf>
+    where F: Fn(&Path) -> bool {
+    debug!("Attempting to extract files of chart `{}` from archive `{}` to `{}`.", chart_name, archive_path.display(), extraction_path.display());
+    extract(archive_path, extraction_path, Some(chart_name), is_wanted, false)
+}
+
+/// Like `extract_chart_files`, for all files of the chart, e.g. to compare two chart versions. The links in the
+/// archive are skipped, since nothing reads the files they point to.
+pub fn extract_all_chart_files(archive_path: &Path, extraction_path: &Path, chart_name: &str) -> Result<PathBuf> {
+    debug!("Attempting to extract all files of chart `{}` from archive `{}` to `{}`.", chart_name, archive_path.display(), extraction_path.display());
+    extract(archive_path, extraction_path, Some(chart_name), |_| true, true)
+}
+
+/// Like `extract_chart_files`, for archives of charts whose name isn't known yet, like the packaged subcharts of a chart.
+/// The chart dir is the only top-level dir with a Chart.yaml.
+pub fn extract_unnamed_chart_files<F>(archive_path: &Path, extraction_path: &Path, is_wanted: F) -> Result<PathBuf>
+    where F: Fn(&Path) -> bool {
+    debug!("Attempting to extract the chart files from archive `{}` to `{}`.", archive_path.display(), extraction_path.display());
+    extract(archive_path, extraction_path, None, is_wanted, false)
+}
+
+// Wanted links are skipped if `skip_links` is set, and are an error otherwise
+fn extract<F>(archive_path: &Path, extraction_path: &Path, chart_name: Option<&str>, is_wanted: F, skip_links: bool) -> Result<PathBuf>
+    where F: Fn(&Path) -> bool {
+    let archive_path_str = archive_path.to_str().unwrap();
+
+    let tar_gz = File::open(archive_path)
+        .with_context(|| format!("Couldn't open file `{}`!", archive_path_str))?;
+    // Skipped entries are decompressed too, so a gzip bomb is stopped by the total size and not only by the sizes of the extracted files
+    // The headers and the padding of the entries come on top of their sizes
+    let mut archive = Archive::new(LimitedReader { inner: GzDecoder::new(tar_gz), remaining: MAX_ARCHIVE_SIZE + (MAX_ENTRIES as u64 + 2) * 1024 });
+    let mut total_size: u64 = 0;
+
+    // top-level dir -> whether it has a Chart.yaml
+    let mut top_level_dirs: BTreeMap<String, bool> = BTreeMap::new();
+
+    let entries = archive.entries()
+        .with_context(|| format!("Couldn't read the entries of archive `{}`!", archive_path_str))?;
+    for (index, entry) in entries.enumerate() {
+        if index >= MAX_ENTRIES {
+            return Err(anyhow::anyhow!("Archive `{}` has more than {} entries!", archive_path_str, MAX_ENTRIES));
+        }
+
+        let mut entry = entry.with_context(|| format!("Couldn't read entry `{}` of archive `{}`!", index, archive_path_str))?;
+        let entry_path = entry.path()
+            .with_context(|| format!("Couldn't read the path of entry `{}` of archive `{}`!", index, archive_path_str))?
+            .into_owned();
+
+        let mut components = Vec::new();
+        for component in entry_path.components() {
+            match component {
+                Component::Normal(name) => components.push(name.to_owned()),
+                Component::CurDir => {}
+                _ => return Err(anyhow::anyhow!("Archive `{}` has an entry with an unsafe path `{}`!", archive_path_str, entry_path.display())),
+            }
+        }
+
+        // PAX headers and the like aren't part of the chart
+        if components.len() < 2 || !(entry.header().entry_type().is_file() || entry.header().entry_type().is_symlink()) {
+            continue;
+        }
+
+        let entry_size = entry.header().size()
+            .with_context(|| format!("Couldn't read the size of entry `{}` of archive `{}`!", entry_path.display(), archive_path_str))?;
+        if entry_size > MAX_FILE_SIZE {
+            return Err(anyhow::anyhow!("File `{}` in archive `{}` is bigger than {} bytes!", entry_path.display(), archive_path_str, MAX_FILE_SIZE));
+        }
+        total_size += entry_size;
+        if total_size > MAX_ARCHIVE_SIZE {
+            return Err(anyhow::anyhow!("The files in archive `{}` are bigger than {} bytes together!", archive_path_str, MAX_ARCHIVE_SIZE));
+        }
+
+        let top_level_dir = components[0].to_string_lossy().into_owned();
+        let relative_path: PathBuf = components[1..].iter().collect();
+        if relative_path == Path::new("Chart.yaml") {
+            top_level_dirs.insert(top_level_dir.clone(), true);
+        } else {
+            top_level_dirs.entry(top_level_dir.clone()).or_insert(false);
+        }
+
+        if !is_wanted(&relative_path) {
+            continue;
+        }
+
+        if entry.header().entry_type().is_symlink() && skip_links {
+            debug!("Skipped link `{}` in archive `{}`.", entry_path.display(), archive_path_str);
+            continue;
+        }
+        if entry.header().entry_type().is_symlink() {
+            return Err(anyhow::anyhow!("Archive `{}` has a link `{}` where a chart file is expected!", archive_path_str, entry_path.display()));
+        }
+
+        let target_path = extraction_path.join(&top_level_dir).join(&relative_path);
+        fs::create_dir_all(target_path.parent().unwrap())
+            .with_context(|| format!("Couldn't create dir for `{}`!", target_path.display()))?;
+        let mut target_file = File::create(&target_path)
+            .with_context(|| format!("Couldn't create file `{}`!", target_path.display()))?;
+
+        // Don't trust the size in the header either
+        let copied_size = io::copy(&mut (&mut entry).take(MAX_FILE_SIZE + 1), &mut target_file)
+            .with_context(|| format!("Failed to extract `{}` from archive `{}`!", entry_path.display(), archive_path_str))?;
+        if copied_size > MAX_FILE_SIZE {
+            return Err(anyhow::anyhow!("File `{}` in archive `{}` is bigger than {} bytes!", entry_path.display(), archive_path_str, MAX_FILE_SIZE));
+        }
+
+        trace!("Extracted `{}` to `{}`.", entry_path.display(), target_path.display());
+    }
+
+    let chart_dir_name = match chart_name {
+        Some(chart_name) if top_level_dirs.get(chart_name) == Some(&true) => String::from(chart_name),
+        _ => {
+            let chart_dirs: Vec<&String> = top_level_dirs.iter()
+                .filter(|(_, has_chart_file)| **has_chart_file)
+                .map(|(top_level_dir, _)| top_level_dir)
+                .collect();
+
+            match (chart_dirs.as_slice(), chart_name) {
+                ([chart_dir_name], Some(chart_name)) => {
+                    debug!("The chart dir in archive `{}` is `{}` instead of `{}`.", archive_path_str, chart_dir_name, chart_name);
+                    String::clone(chart_dir_name)
+                }
+                ([chart_dir_name], None) => String::clone(chart_dir_name),
+                ([], _) => return Err(anyhow::anyhow!("Archive `{}` doesn't contain a chart!", archive_path_str)),
+                (_, Some(chart_name)) => return Err(anyhow::anyhow!("Archive `{}` contains multiple charts and none of them is in dir `{}`!", archive_path_str, chart_name)),
+                (_, None) => return Err(anyhow::anyhow!("Archive `{}` contains multiple charts!", archive_path_str)),
+            }
+        }
+    };
+
+    let chart_dir_path = extraction_path.join(chart_dir_name);
+    debug!("Chart files were extracted successfully from archive `{}` to `{}`.", archive_path_str, chart_dir_path.display());
+    Ok(chart_dir_path)
+}
+
+// Fails once more than `remaining` bytes were read, unlike `Read::take`, which would just end the archive early
+struct LimitedReader<R> {
+    inner: R,
+    remaining: u64,
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read_size = self.inner.read(buf)?;
+        self.remaining = self.remaining.checked_sub(read_size as u64)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("the archive is bigger than {} bytes uncompressed", MAX_ARCHIVE_SIZE)))?;
+        Ok(read_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use tar::{Builder, EntryType, Header};
+    use tempfile::TempDir;
+
+    // The paths are written as they are, `tar::Builder` would refuse the unsafe ones
+    fn write_archive(dir: &TempDir, entries: &[(&str, EntryType, &[u8])]) -> PathBuf {
+        let archive_path = dir.path().join("chart.tgz");
+        let mut builder = Builder::new(GzEncoder::new(File::create(&archive_path).unwrap(), Compression::fast()));
+        for (path, entry_type, data) in entries {
+            let mut header = Header::new_gnu();
+            header.as_gnu_mut().unwrap().name[..path.len()].copy_from_slice(path.as_bytes());
+            header.set_entry_type(*entry_type);
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            if entry_type.is_symlink() {
+                header.set_link_name("../../etc/passwd").unwrap();
+            }
+            header.set_cksum();
+            builder.append(&header, *data).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+        archive_path
+    }
+
+    fn extract(entries: &[(&str, EntryType, &[u8])], is_wanted: fn(&Path) -> bool) -> (TempDir, Result<PathBuf>) {
+        let dir = TempDir::new().unwrap();
+        let archive_path = write_archive(&dir, entries);
+        let extraction_path = dir.path().join("extracted");
+        let result = extract_chart_files(&archive_path, &extraction_path, "mychart", is_wanted);
+        (dir, result)
+    }
+
+    #[test]
+    fn extracts_only_wanted_files() {
+        let (_dir, result) = extract(&[
+            ("mychart/Chart.yaml", EntryType::Regular, b"name: mychart\n"),
+            ("mychart/values.yaml", EntryType::Regular, b"replicas: 1\n"),
+            ("mychart/templates/deployment.yaml", EntryType::Regular, b"kind: Deployment\n"),
+        ], |path| path == Path::new("values.yaml"));
+
+        let chart_dir_path = result.unwrap();
+        assert_eq!(fs::read_to_string(chart_dir_path.join("values.yaml")).unwrap(), "replicas: 1\n");
+        assert!(!chart_dir_path.join("templates/deployment.yaml").exists());
+    }
+
+    #[test]
+    fn rejects_parent_dir_paths() {
+        let (dir, result) = extract(&[("mychart/../../evil.yaml", EntryType::Regular, b"evil: true\n")], |_| true);
+        assert!(result.unwrap_err().to_string().contains("unsafe path"));
+        assert!(!dir.path().join("evil.yaml").exists());
+    }
+
+    #[test]
+    fn rejects_absolute_paths() {
+        let (_dir, result) = extract(&[("/tmp/hmum-evil.yaml", EntryType::Regular, b"evil: true\n")], |_| true);
+        assert!(result.unwrap_err().to_string().contains("unsafe path"));
+        assert!(!Path::new("/tmp/hmum-evil.yaml").exists());
+    }
+
+    #[test]
+    fn rejects_wanted_symlinks() {
+        let (_dir, result) = extract(&[
+            ("mychart/Chart.yaml", EntryType::Regular, b"name: mychart\n"),
+            ("mychart/values.yaml", EntryType::Symlink, b""),
+        ], |_| true);
+        assert!(result.unwrap_err().to_string().contains("has a link"));
+    }
+
+    #[test]
+    fn ignores_unwanted_symlinks() {
+        let (_dir, result) = extract(&[
+            ("mychart/Chart.yaml", EntryType::Regular, b"name: mychart\n"),
+            ("mychart/templates/link.yaml", EntryType::Symlink, b""),
+        ], |path| path == Path::new("Chart.yaml"));
+        assert!(result.unwrap().join("Chart.yaml").exists());
+    }
+
+    #[test]
+    fn skips_symlinks_when_extracting_all_files() {
+        let dir = TempDir::new().unwrap();
+        let archive_path = write_archive(&dir, &[
+            ("mychart/Chart.yaml", EntryType::Regular, b"name: mychart\n"),
+            ("mychart/templates/link.yaml", EntryType::Symlink, b""),
+            ("mychart/templates/service.yaml", EntryType::Regular, b"kind: Service\n"),
+        ]);
+        let chart_dir_path = extract_all_chart_files(&archive_path, &dir.path().join("extracted"), "mychart").unwrap();
+        assert!(chart_dir_path.join("templates/service.yaml").exists());
+        assert!(fs::symlink_metadata(chart_dir_path.join("templates/link.yaml")).is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_files_even_if_unwanted() {
+        let oversized_data = vec![b'a'; MAX_FILE_SIZE as usize + 1];
+        let (_dir, result) = extract(&[
+            ("mychart/Chart.yaml", EntryType::Regular, b"name: mychart\n"),
+            ("mychart/big.bin", EntryType::Regular, &oversized_data),
+        ], |path| path == Path::new("Chart.yaml"));
+        assert!(result.unwrap_err().to_string().contains("bigger than"));
+    }
+
+    #[test]
+    fn limited_reader_fails_past_the_limit() {
+        let mut limited_reader = LimitedReader { inner: &[0u8; 10][..], remaining: 5 };
+        let error = io::copy(&mut limited_reader, &mut io::sink()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn requires_a_chart_file_in_the_dir_named_after_the_chart() {
+        let (_dir, result) = extract(&[
+            ("mychart/README.md", EntryType::Regular, b"# Not a chart\n"),
+            ("other/Chart.yaml", EntryType::Regular, b"name: mychart\n"),
+        ], |_| true);
+        assert!(result.unwrap().ends_with("other"));
+    }
+
+    #[test]
+    fn finds_the_chart_dir_of_unnamed_charts() {
+        let dir = TempDir::new().unwrap();
+        let archive_path = write_archive(&dir, &[
+            ("redis/Chart.yaml", EntryType::Regular, b"name: redis\n"),
+            ("redis/values.yaml", EntryType::Regular, b"replicas: 1\n"),
+        ]);
+        let chart_dir_path = extract_unnamed_chart_files(&archive_path, &dir.path().join("extracted"), |_| true).unwrap();
+        assert!(chart_dir_path.ends_with("redis"));
+
+        let archive_path = write_archive(&dir, &[
+            ("redis/Chart.yaml", EntryType::Regular, b"name: redis\n"),
+            ("other/Chart.yaml", EntryType::Regular, b"name: other\n"),
+        ]);
+        assert!(extract_unnamed_chart_files(&archive_path, &dir.path().join("extracted"), |_| true).is_err());
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+use anyhow::{Context, Result};
+use log::debug;
+
+use crate::text_diff;
+
+// Compares every file of two extracted chart versions: templates, helpers, CRDs and the rest, not only the values.
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum FileChange {
+    Added,
+    Removed,
+    Modified,
+}
+
+/// A file that differs between two chart versions, with its unified diff.
+#[derive(Debug)]
+pub struct FileDiff {
+    // Relative to the chart dir, with `/` separators
+    pub path: String,
+    pub change: FileChange,
+    pub diff: String,
+}
+
+/// Returns the files that were added, removed or modified between the chart dirs, ordered by path.
+pub fn diff_chart_dirs(original_chart_dir_path: &Path, latest_chart_dir_path: &Path) -> Result<Vec<FileDiff>> {
+    debug!("Attempting to compare the files of chart dirs `{}` and `{}`.", original_chart_dir_path.display(), latest_chart_dir_path.display());
+
+    let original_file_paths = list_files(original_chart_dir_path)?;
+    let latest_file_paths = list_files(latest_chart_dir_path)?;
+
+    let mut file_diffs = Vec::new();
+    for file_path in original_file_paths.union(&latest_file_paths) {
+        let original_file_path = Some(original_chart_dir_path.join(file_path)).filter(|_| original_file_paths.contains(file_path));
+        let latest_file_path = Some(latest_chart_dir_path.join(file_path)).filter(|_| latest_file_paths.contains(file_path));
+        let change = match (&original_file_path, &latest_file_path) {
+            (None, _) => FileChange::Added,
+            (_, None) => FileChange::Removed,
+            _ => FileChange::Modified,
+        };
+
+        let path = file_path.iter().map(|component| component.to_string_lossy()).collect::<Vec<_>>().join("/");
+        if let Some(diff) = text_diff::unified_diff(original_file_path.as_deref(), latest_file_path.as_deref(), &path)? {
+            file_diffs.push(FileDiff { path, change, diff });
+        }
+    }
+
+    debug!("{} file(s) differ between the chart versions.", file_diffs.len());
+    Ok(file_diffs)
+}
+
+/// Returns the paths of all files under `dir_path`, relative to it.
+pub fn list_files(dir_path: &Path) -> Result<BTreeSet<PathBuf>> {
+    let mut file_paths = BTreeSet::new();
+    let mut pending_dir_paths = vec![dir_path.to_path_buf()];
+    while let Some(pending_dir_path) = pending_dir_paths.pop() {
+        let entries = fs::read_dir(&pending_dir_path)
+            .with_context(|| format!("Couldn't read dir `{}`!", pending_dir_path.display()))?;
+        for entry in entries {
+            let entry_path = entry.with_context(|| format!("Couldn't read an entry of dir `{}`!", pending_dir_path.display()))?.path();
+            if entry_path.is_dir() {
+                pending_dir_paths.push(entry_path);
+            } else if let Ok(relative_path) = entry_path.strip_prefix(dir_path) {
+                file_paths.insert(relative_path.to_path_buf());
+            }
+        }
+    }
+    Ok(file_paths)
+}
+
+/// Whether the file is a template, whose diffs go into the reports.
+pub fn is_template(path: &str) -> bool {
+    path.starts_with("templates/")
+}
+
+/// The name of the file the full diff of an app's chart update is written to. App names and versions come from the DSF
+/// and the index.yaml, so path separators and `..` in them are replaced to keep the file in the chart diff dir.
+pub fn diff_file_name(app_name: &str, chart_name: &str, original_version: &str, latest_version: &str) -> String {
+    let components: Vec<String> = [app_name, chart_name, original_version, latest_version].iter()
+        .map(|component| component.replace(['/', '\\'], "_").replace("..", "_"))
+        .collect();
+    format!("{}.diff", components.join("-"))
+}
+
+/// Cuts `diff` after `max_lines` lines, noting how many lines were left out and where to find them.
+pub fn truncate_diff(diff: &str, max_lines: usize, full_diff_path: &Path) -> (String, bool) {
+    let line_count = diff.lines().count();
+    if line_count <= max_lines {
+        return (String::from(diff), false);
+    }
+    let mut truncated_diff: String = diff.split_inclusive('\n').take(max_lines).collect();
+    truncated_diff.push_str(&format!("... ({} more lines in {})\n", line_count - max_lines, full_diff_path.display()));
+    (truncated_diff, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_diff_files_after_the_app_and_versions() {
+        assert_eq!(diff_file_name("web", "nginx", "1.0.0", "2.0.0"), "web-nginx-1.0.0-2.0.0.diff");
+    }
+
+    #[test]
+    fn keeps_diff_files_in_the_diff_dir() {
+        let diff_file_name = diff_file_name("../../etc/web", "nginx", "1.0.0/..", "..\\2.0.0");
+        assert!(!diff_file_name.contains('/') && !diff_file_name.contains('\\') && !diff_file_name.contains(".."));
+        assert_eq!(Path::new("diffs").join(&diff_file_name).parent(), Some(Path::new("diffs")));
+    }
+}
@@ -0,0 +1,186 @@
+use log::debug;
+
+// For charts without release notes in their annotations, their docs tell what changed: the new entries of the
+// CHANGELOG.md, the parameters tables of the README.md, which list the values with their defaults, and NOTES.txt.
+
+/// The files of a chart archive the docs are read from, relative to the chart dir.
+pub const CHANGELOG_FILE: &str = "CHANGELOG.md";
+pub const README_FILE: &str = "README.md";
+pub const NOTES_FILE: &str = "templates/NOTES.txt";
+
+// Changelogs of charts that are far behind can be long, the rest is in the file
+const MAX_CHANGELOG_LINES: usize = 200;
+
+/// A row of a parameters table that was added, removed or changed, without the parameter in the cells.
+#[derive(Debug)]
+pub struct ParameterChange {
+    pub parameter: String,
+    pub original_row: Option<String>,
+    pub latest_row: Option<String>,
+}
+
+/// Returns the entries of the latest changelog that are newer than the current version: the ones before the first
+/// entry of the original changelog or, without it, before the entry of `current_version`.
+pub fn changelog_excerpt(original_changelog: Option<&str>, latest_changelog: &str, current_version: &str) -> Option<String> {
+    let latest_lines: Vec<&str> = latest_changelog.lines().collect();
+    let first_entry = latest_lines.iter().position(|line| is_version_heading(line))?;
+
+    let original_first_heading = original_changelog.and_then(|original_changelog| original_changelog.lines().find(|line| is_version_heading(line)));
+    let known_entry = original_first_heading
+        .and_then(|original_first_heading| latest_lines.iter().position(|line| *line == original_first_heading))
+        .or_else(|| latest_lines.iter().position(|line| is_version_heading(line) && heading_mentions_version(line, current_version)));
+    let known_entry = match known_entry {
+        Some(known_entry) if known_entry > first_entry => known_entry,
+        Some(_) => return None,
+        None => {
+            debug!("Couldn't find where the changelog entries of version `{}` start.", current_version);
+            return None;
+        }
+    };
+
+    let new_lines = &latest_lines[first_entry..known_entry];
+    let mut excerpt: String = new_lines.iter().take(MAX_CHANGELOG_LINES).map(|line| format!("{}\n", line)).collect();
+    if new_lines.len() > MAX_CHANGELOG_LINES {
+        excerpt.push_str(&format!("... ({} more lines in {})\n", new_lines.len() - MAX_CHANGELOG_LINES, CHANGELOG_FILE));
+    }
+    Some(excerpt.trim_end().to_string() + "\n")
+}
+
+// Headings like `## 1.2.0`, `## [v1.2.0] - 2020-01-01` or `# Version 1.2.0`
+fn is_version_heading(line: &str) -> bool {
+    line.starts_with('#') && line.split(|c: char| !is_version_char(c)).any(is_version)
+}
+
+fn heading_mentions_version(line: &str, version: &str) -> bool {
+    line.split(|c: char| !is_version_char(c)).any(|word| word.trim_start_matches('v') == version.trim_start_matches('v'))
+}
+
+fn is_version_char(c: char) -> bool {
+    c.is_alphanumeric() || ".-+".contains(c)
+}
+
+fn is_version(word: &str) -> bool {
+    let word = word.trim_start_matches('v');
+    word.contains('.') && word.starts_with(|c: char| c.is_ascii_digit())
+}
+
+/// Compares the parameters tables of two READMEs row by row, by the parameter in their first column.
+pub fn diff_parameter_tables(original_readme: &str, latest_readme: &str) -> Vec<ParameterChange> {
+    let original_rows = parameter_rows(original_readme);
+    let latest_rows = parameter_rows(latest_readme);
+
+    let mut parameter_changes = Vec::new();
+    for (parameter, latest_row) in &latest_rows {
+        match original_rows.iter().find(|(original_parameter, _)| original_parameter == parameter) {
+            Some((_, original_row)) if original_row == latest_row => {}
+            original => parameter_changes.push(ParameterChange {
+                parameter: parameter.clone(),
+                original_row: original.map(|(_, original_row)| original_row.clone()),
+                latest_row: Some(latest_row.clone()),
+            }),
+        }
+    }
+    for (parameter, original_row) in &original_rows {
+        if !latest_rows.iter().any(|(latest_parameter, _)| latest_parameter == parameter) {
+            parameter_changes.push(ParameterChange { parameter: parameter.clone(), original_row: Some(original_row.clone()), latest_row: None });
+        }
+    }
+
+    parameter_changes
+}
+
+// The rows of the Markdown tables whose first column is the parameter, like `| Name | Description | Value |`
+fn parameter_rows(readme: &str) -> Vec<(String, String)> {
+    let mut rows = Vec::new();
+    let mut in_parameters_table = false;
+    let mut previous_line_is_row = false;
+    for line in readme.lines().map(str::trim) {
+        let is_row = line.starts_with('|');
+        if !is_row {
+            in_parameters_table = false;
+        } else if !previous_line_is_row {
+            // The header row decides what the table is about
+            let header = split_row(line);
+            in_parameters_table = header.first().is_some_and(|first_column| {
+                ["parameter", "parameters", "name", "key", "value"].contains(&first_column.trim_matches(['*', '`']).to_lowercase().as_str())
+            });
+        } else if in_parameters_table && !line.trim_matches(['|', '-', ':', ' ']).is_empty() {
+            let cells = split_row(line);
+            if let Some((parameter, other_cells)) = cells.split_first() {
+                rows.push((parameter.trim_matches('`').to_string(), other_cells.join(" | ")));
+            }
+        }
+        previous_line_is_row = is_row;
+    }
+    rows
+}
+
+// `| a | b \| c |` has the cells `a` and `b | c`
+fn split_row(line: &str) -> Vec<String> {
+    line.trim().trim_start_matches('|').trim_end_matches('|')
+        .replace("\\|", "\u{0}")
+        .split('|')
+        .map(|cell| cell.trim().replace('\u{0}', "|"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LATEST_CHANGELOG: &str = "# Changelog\n\n## [v1.3.0] - 2021-03-01\n\n- Add sidecars\n\n## 1.2.0\n\n- Fix the ingress\n\n## 1.1.0\n\n- Initial release\n";
+
+    #[test]
+    fn cuts_the_changelog_at_the_current_version() {
+        assert_eq!(changelog_excerpt(None, LATEST_CHANGELOG, "1.1.0").unwrap(), "## [v1.3.0] - 2021-03-01\n\n- Add sidecars\n\n## 1.2.0\n\n- Fix the ingress\n");
+        assert_eq!(changelog_excerpt(None, LATEST_CHANGELOG, "v1.2.0").unwrap(), "## [v1.3.0] - 2021-03-01\n\n- Add sidecars\n");
+        // Nothing newer, or no entry for the current version
+        assert!(changelog_excerpt(None, LATEST_CHANGELOG, "1.3.0").is_none());
+        assert!(changelog_excerpt(None, LATEST_CHANGELOG, "0.9.0").is_none());
+    }
+
+    #[test]
+    fn cuts_the_changelog_at_the_first_entry_of_the_original_one() {
+        // The original changelog decides, even when its first entry isn't the current version's
+        let original_changelog = "# Changelog\n\n## 1.2.0\n\n- Fix the ingress\n";
+        assert_eq!(changelog_excerpt(Some(original_changelog), LATEST_CHANGELOG, "1.1.0").unwrap(), "## [v1.3.0] - 2021-03-01\n\n- Add sidecars\n");
+    }
+
+    const ORIGINAL_README: &str = concat!(
+        "# Chart\n\n",
+        "| Name | Description | Value |\n",
+        "|------|-------------|-------|\n",
+        "| `replicas` | Number of pods | `1` |\n",
+        "| `image.tag` | Image tag | `1.19` |\n",
+        "| `mode` | One of `a \\| b` | `a` |\n",
+        "| `legacy` | Removed soon | `false` |\n",
+        "\n",
+        "| Other | Table |\n",
+        "|-------|-------|\n",
+        "| not | a parameter |\n",
+    );
+
+    #[test]
+    fn diffs_parameter_tables_by_parameter() {
+        let latest_readme = ORIGINAL_README
+            .replace("`1.19`", "`1.21`")
+            .replace("`a \\| b` | `a`", "`a \\| b \\| c` | `a`")
+            .replace("| `legacy` | Removed soon | `false` |\n", "| `sidecars` | Extra containers | `[]` |\n");
+
+        let parameter_changes = diff_parameter_tables(ORIGINAL_README, &latest_readme);
+        let parameter_changes: Vec<(&str, Option<&str>, Option<&str>)> = parameter_changes.iter()
+            .map(|parameter_change| (parameter_change.parameter.as_str(), parameter_change.original_row.as_deref(), parameter_change.latest_row.as_deref()))
+            .collect();
+        assert_eq!(parameter_changes, vec![
+            ("image.tag", Some("Image tag | `1.19`"), Some("Image tag | `1.21`")),
+            ("mode", Some("One of `a | b` | `a`"), Some("One of `a | b | c` | `a`")),
+            ("sidecars", None, Some("Extra containers | `[]`")),
+            ("legacy", Some("Removed soon | `false`"), None),
+        ]);
+    }
+
+    #[test]
+    fn splits_rows_on_unescaped_pipes() {
+        assert_eq!(split_row("| `a` | b \\| c |"), vec!["`a`", "b | c"]);
+    }
+}
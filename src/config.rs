@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use serde::Deserialize;
+use anyhow::{Context, Result};
+use log::{debug, trace};
+
+use crate::helm;
+
+// Optional hmum configuration file, passed with `--config`, for settings that apply to a single repo or app.
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub keyring: Option<PathBuf>,
+    #[serde(default)]
+    pub repos: HashMap<String, RepoConfig>,
+    #[serde(default)]
+    pub apps: HashMap<String, AppConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct RepoConfig {
+    #[serde(default)]
+    pub verify_provenance: bool,
+    #[serde(default)]
+    pub keyring: Option<PathBuf>,
+    #[serde(default)]
+    pub mirrors: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct AppConfig {
+    #[serde(default)]
+    pub verify_provenance: Option<bool>,
+    #[serde(default)]
+    pub keyring: Option<PathBuf>,
+    // `valuesFiles` entries that only override a few keys, as opposed to full copies of the chart's values.yaml
+    #[serde(default)]
+    pub overlay_values_files: Vec<String>,
+    // How to resolve merge conflicts on specific keys, the first policy whose path matches wins
+    #[serde(default)]
+    pub merge_policies: Vec<MergePolicy>,
+    // Overrides `--conflict-output` for the app
+    #[serde(default)]
+    pub conflict_output: Option<ConflictOutput>,
+    // Overrides `--merge-steps` for the app
+    #[serde(default)]
+    pub merge_steps: Option<MergeSteps>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct MergePolicy {
+    /// Key path like `image.tag`, where `*` matches any single key and `**` any number of keys, and keys with dots are quoted
+    pub path: String,
+    pub resolve: Resolution,
+}
+
+/// What to do with merge conflicts that no merge policy resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ConflictOutput {
+    /// Conflict markers in the values file
+    Markers,
+    /// Conflict markers in the values file, including the original upstream lines
+    Diff3,
+    /// Conflict markers in a `.hmum-conflict` file next to the values file, which stays as it was
+    Sidecar,
+    /// Our side of every conflict in the values file, with the conflicts only in the reports
+    // `keep-ours` like on the command line
+    #[serde(alias = "keep-ours")]
+    KeepOurs,
+}
+
+impl FromStr for ConflictOutput {
+    type Err = anyhow::Error;
+
+    fn from_str(conflict_output: &str) -> Result<ConflictOutput> {
+        match conflict_output {
+            "markers" => Ok(ConflictOutput::Markers),
+            "diff3" => Ok(ConflictOutput::Diff3),
+            "sidecar" => Ok(ConflictOutput::Sidecar),
+            "keep-ours" | "keepOurs" => Ok(ConflictOutput::KeepOurs),
+            _ => Err(anyhow::anyhow!("Unknown conflict output `{}`! Use `markers`, `diff3`, `sidecar` or `keep-ours`.", conflict_output)),
+        }
+    }
+}
+
+/// Which chart versions the values files are merged through on the way to the latest one.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MergeSteps {
+    /// Straight from the current to the latest version
+    Direct,
+    /// Through the last version of every major version in between
+    Major,
+    /// Through the last version of every minor version in between
+    Minor,
+}
+
+impl FromStr for MergeSteps {
+    type Err = anyhow::Error;
+
+    fn from_str(merge_steps: &str) -> Result<MergeSteps> {
+        match merge_steps {
+            "direct" => Ok(MergeSteps::Direct),
+            "major" => Ok(MergeSteps::Major),
+            "minor" => Ok(MergeSteps::Minor),
+            _ => Err(anyhow::anyhow!("Unknown merge steps `{}`! Use `direct`, `major` or `minor`.", merge_steps)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Resolution {
+    Ours,
+    Theirs,
+    OursUnlessUpstreamChanged,
+}
+
+impl Config {
+    pub fn load(config_path: &Path) -> Result<Config> {
+        debug!("Attempting to read hmum config file `{}`.", config_path.display());
+
+        let config_file = File::open(config_path)
+            .with_context(|| format!("Could not open file `{}`", config_path.display()))?;
+        let mut config: Config = serde_yaml::from_reader(BufReader::new(config_file))
+            .with_context(|| format!("Could not parse hmum config file `{}`!", config_path.display()))?;
+
+        // Paths in the config are relative to the config file, the same as values files are relative to their helmsman DSF
+        let config_parent_path = config_path.parent().unwrap_or_else(|| Path::new(""));
+        let resolve = |path: &mut Option<PathBuf>| {
+            if let Some(relative_path) = path.as_ref().filter(|path| path.is_relative()) {
+                *path = Some(config_parent_path.join(relative_path));
+            }
+        };
+        resolve(&mut config.keyring);
+        config.repos.values_mut().for_each(|repo_config| resolve(&mut repo_config.keyring));
+        config.apps.values_mut().for_each(|app_config| resolve(&mut app_config.keyring));
+
+        trace!("Read the following hmum config: `{:?}`.", config);
+        Ok(config)
+    }
+
+    pub fn repo_mirrors(&self, repo_name: &str) -> &[String] {
+        self.repos.get(repo_name).map_or(&[], |repo_config| &repo_config.mirrors)
+    }
+
+    /// Overlay values files aren't merged with the chart's values.yaml, they are only checked for keys the chart removed.
+    pub fn is_overlay_values_file(&self, app_name: &str, values_file: &str) -> bool {
+        self.apps.get(app_name)
+            .is_some_and(|app_config| app_config.overlay_values_files.iter().any(|overlay_values_file| overlay_values_file == values_file))
+    }
+
+    pub fn conflict_output(&self, app_name: &str, default_conflict_output: ConflictOutput) -> ConflictOutput {
+        self.apps.get(app_name)
+            .and_then(|app_config| app_config.conflict_output)
+            .unwrap_or(default_conflict_output)
+    }
+
+    pub fn merge_steps(&self, app_name: &str, default_merge_steps: MergeSteps) -> MergeSteps {
+        self.apps.get(app_name)
+            .and_then(|app_config| app_config.merge_steps)
+            .unwrap_or(default_merge_steps)
+    }
+
+    pub fn merge_policies(&self, app_name: &str) -> &[MergePolicy] {
+        self.apps.get(app_name).map_or(&[], |app_config| &app_config.merge_policies)
+    }
+
+    /// Returns the keyring to verify the provenance of the app's charts with, or `None` if the app's charts
+    /// don't need to be verified. App settings take precedence over the settings of the app's repo.
+    pub fn provenance_keyring(&self, app_name: &str, repo_name: &str) -> Option<PathBuf> {
+        let app_config = self.apps.get(app_name);
+        let repo_config = self.repos.get(repo_name);
+
+        let verify_provenance = app_config.and_then(|app_config| app_config.verify_provenance)
+            .unwrap_or_else(|| repo_config.is_some_and(|repo_config| repo_config.verify_provenance));
+        if !verify_provenance {
+            return None;
+        }
+
+        app_config.and_then(|app_config| app_config.keyring.clone())
+            .or_else(|| repo_config.and_then(|repo_config| repo_config.keyring.clone()))
+            .or_else(|| self.keyring.clone())
+            .or_else(helm::default_keyring_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_conflict_outputs_like_the_command_line() {
+        for conflict_output in ["markers", "diff3", "sidecar", "keep-ours", "keepOurs"] {
+            let from_config: ConflictOutput = serde_yaml::from_str(conflict_output).unwrap();
+            assert_eq!(from_config, conflict_output.parse::<ConflictOutput>().unwrap());
+        }
+    }
+}
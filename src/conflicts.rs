@@ -0,0 +1,305 @@
+use serde::Serialize;
+use serde_yaml::Value;
+use log::debug;
+
+use crate::config::{MergePolicy, Resolution};
+use crate::values::{self, KeyPath};
+use crate::yaml_text::KeyPathTracker;
+
+// The output of `git merge-file`, split into the parts both sides agree on and the conflicts, so conflicts can be
+// resolved by the merge policies before anything is written.
+
+const OURS_MARKER: &str = "<<<<<<<";
+const BASE_MARKER: &str = "|||||||";
+const SEPARATOR_MARKER: &str = "=======";
+const THEIRS_MARKER: &str = ">>>>>>>";
+
+#[derive(Debug)]
+pub enum Chunk<'a> {
+    Common(Vec<&'a str>),
+    Conflict(Conflict<'a>),
+}
+
+#[derive(Debug)]
+pub struct Conflict<'a> {
+    // The marker lines, kept to write the conflict back exactly as git wrote it
+    pub ours_marker: &'a str,
+    pub ours: Vec<&'a str>,
+    pub base_marker: Option<&'a str>,
+    pub base: Vec<&'a str>,
+    pub separator_marker: &'a str,
+    pub theirs: Vec<&'a str>,
+    pub theirs_marker: &'a str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Side {
+    Ours,
+    Theirs,
+}
+
+/// A conflict that is left for a human, with both sides as they are in the files.
+#[derive(Debug)]
+pub struct ConflictDescription {
+    pub key_paths: Vec<KeyPath>,
+    pub ours: String,
+    pub theirs: String,
+}
+
+/// A conflict that a merge policy resolved.
+#[derive(Debug)]
+pub struct AutoResolution {
+    pub key_paths: Vec<KeyPath>,
+    pub side: Side,
+    pub policies: Vec<String>,
+}
+
+fn is_marker(line: &str, marker: &str) -> bool {
+    line.strip_prefix(marker)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(' ') || rest.starts_with('\n') || rest.starts_with('\r'))
+}
+
+pub fn parse_merged_text(merged_text: &str) -> Vec<Chunk<'_>> {
+    let mut chunks = Vec::new();
+    let mut common_lines = Vec::new();
+    let mut lines = merged_text.split_inclusive('\n');
+
+    while let Some(line) = lines.next() {
+        if !is_marker(line, OURS_MARKER) {
+            common_lines.push(line);
+            continue;
+        }
+
+        let mut conflict = Conflict {
+            ours_marker: line,
+            ours: Vec::new(),
+            base_marker: None,
+            base: Vec::new(),
+            separator_marker: "",
+            theirs: Vec::new(),
+            theirs_marker: "",
+        };
+        for line in lines.by_ref() {
+            if conflict.separator_marker.is_empty() {
+                if is_marker(line, BASE_MARKER) {
+                    conflict.base_marker = Some(line);
+                } else if is_marker(line, SEPARATOR_MARKER) {
+                    conflict.separator_marker = line;
+                } else if conflict.base_marker.is_some() {
+                    conflict.base.push(line);
+                } else {
+                    conflict.ours.push(line);
+                }
+            } else if is_marker(line, THEIRS_MARKER) {
+                conflict.theirs_marker = line;
+                break;
+            } else {
+                conflict.theirs.push(line);
+            }
+        }
+
+        if !common_lines.is_empty() {
+            chunks.push(Chunk::Common(std::mem::take(&mut common_lines)));
+        }
+        chunks.push(Chunk::Conflict(conflict));
+    }
+    if !common_lines.is_empty() {
+        chunks.push(Chunk::Common(common_lines));
+    }
+
+    chunks
+}
+
+pub fn render(chunks: &[Chunk]) -> String {
+    let mut text = String::new();
+    for chunk in chunks {
+        match chunk {
+            Chunk::Common(lines) => lines.iter().for_each(|line| text.push_str(line)),
+            Chunk::Conflict(conflict) => {
+                text.push_str(conflict.ours_marker);
+                conflict.ours.iter().for_each(|line| text.push_str(line));
+                if let Some(base_marker) = conflict.base_marker {
+                    text.push_str(base_marker);
+                    conflict.base.iter().for_each(|line| text.push_str(line));
+                }
+                text.push_str(conflict.separator_marker);
+                conflict.theirs.iter().for_each(|line| text.push_str(line));
+                text.push_str(conflict.theirs_marker);
+            }
+        }
+    }
+    text
+}
+
+/// Resolves the conflicts for which every key they touch has a merge policy and all the policies pick the same side.
+/// `original_values` and `latest_values` are the chart's default values, which `oursUnlessUpstreamChanged` compares.
+pub fn resolve_conflicts(chunks: &mut [Chunk], merge_policies: &[MergePolicy], original_values: &Value, latest_values: &Value) -> Vec<AutoResolution> {
+    let mut auto_resolutions = Vec::new();
+    let mut tracker = KeyPathTracker::default();
+
+    for chunk in chunks.iter_mut() {
+        let conflict = match chunk {
+            Chunk::Common(lines) => {
+                lines.iter().for_each(|line| { tracker.track(line); });
+                continue;
+            }
+            Chunk::Conflict(conflict) => conflict,
+        };
+
+        let (key_paths, theirs_tracker) = track_conflict(&mut tracker, conflict);
+
+        let decisions: Option<Vec<(Side, &str)>> = key_paths.iter()
+            .map(|key_path| decide(key_path, merge_policies, original_values, latest_values))
+            .collect();
+        let side = match decisions.as_deref() {
+            Some([(side, _), rest @ ..]) if rest.iter().all(|(other_side, _)| other_side == side) => *side,
+            _ => {
+                debug!("Conflict on keys `{:?}` can't be resolved by the merge policies.", key_paths);
+                continue;
+            }
+        };
+
+        let mut policies: Vec<String> = Vec::new();
+        for (_, policy) in decisions.unwrap_or_default() {
+            if !policies.iter().any(|known_policy| known_policy == policy) {
+                policies.push(String::from(policy));
+            }
+        }
+
+        let lines = match side {
+            Side::Ours => std::mem::take(&mut conflict.ours),
+            Side::Theirs => {
+                tracker = theirs_tracker;
+                std::mem::take(&mut conflict.theirs)
+            }
+        };
+        *chunk = Chunk::Common(lines);
+        auto_resolutions.push(AutoResolution { key_paths, side, policies });
+    }
+
+    auto_resolutions
+}
+
+pub fn describe_conflicts(chunks: &[Chunk]) -> Vec<ConflictDescription> {
+    let mut conflict_descriptions = Vec::new();
+    let mut tracker = KeyPathTracker::default();
+
+    for chunk in chunks {
+        match chunk {
+            Chunk::Common(lines) => lines.iter().for_each(|line| { tracker.track(line); }),
+            Chunk::Conflict(conflict) => {
+                let (key_paths, _) = track_conflict(&mut tracker, conflict);
+                conflict_descriptions.push(ConflictDescription { key_paths, ours: conflict.ours.concat(), theirs: conflict.theirs.concat() });
+            }
+        }
+    }
+
+    conflict_descriptions
+}
+
+/// Replaces every conflict with our side of it.
+pub fn keep_ours(chunks: &mut [Chunk]) {
+    for chunk in chunks.iter_mut() {
+        if let Chunk::Conflict(conflict) = chunk {
+            *chunk = Chunk::Common(std::mem::take(&mut conflict.ours));
+        }
+    }
+}
+
+// Returns the innermost keys the conflict touches on either side, so that a conflict that includes the `image:` line
+// as well is still about `image.tag`. `tracker` continues after our side and the returned tracker after theirs.
+fn track_conflict(tracker: &mut KeyPathTracker, conflict: &Conflict) -> (Vec<KeyPath>, KeyPathTracker) {
+    let mut theirs_tracker = tracker.clone();
+    let our_key_paths = values::innermost_key_paths(conflict.ours.iter().filter_map(|line| tracker.track(line)).collect());
+    let their_key_paths = values::innermost_key_paths(conflict.theirs.iter().filter_map(|line| theirs_tracker.track(line)).collect());
+
+    let mut key_paths: Vec<KeyPath> = Vec::new();
+    for key_path in our_key_paths.into_iter().chain(their_key_paths) {
+        if !key_paths.contains(&key_path) {
+            key_paths.push(key_path);
+        }
+    }
+    (key_paths, theirs_tracker)
+}
+
+fn decide<'p>(key_path: &[String], merge_policies: &'p [MergePolicy], original_values: &Value, latest_values: &Value) -> Option<(Side, &'p str)> {
+    let merge_policy = merge_policies.iter().find(|merge_policy| values::key_path_matches(&merge_policy.path, key_path))?;
+
+    let side = match merge_policy.resolve {
+        Resolution::Ours => Side::Ours,
+        Resolution::Theirs => Side::Theirs,
+        Resolution::OursUnlessUpstreamChanged => {
+            if values::get_key_path(original_values, key_path) != values::get_key_path(latest_values, key_path) {
+                Side::Theirs
+            } else {
+                Side::Ours
+            }
+        }
+    };
+    Some((side, &merge_policy.path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MERGED_TEXT: &str = "replicas: 1\n<<<<<<< ours\nimage:\n  tag: \"1.0\"\n||||||| base\nimage:\n  tag: \"0.9\"\n=======\nimage:\n  tag: \"2.0\"\n>>>>>>> theirs\nservice:\n  port: 80\n";
+
+    fn merge_policy(path: &str, resolve: Resolution) -> MergePolicy {
+        MergePolicy { path: String::from(path), resolve }
+    }
+
+    #[test]
+    fn parses_and_renders_conflicts() {
+        let chunks = parse_merged_text(MERGED_TEXT);
+        assert_eq!(chunks.len(), 3);
+        match &chunks[1] {
+            Chunk::Conflict(conflict) => {
+                assert_eq!(conflict.ours, vec!["image:\n", "  tag: \"1.0\"\n"]);
+                assert_eq!(conflict.base, vec!["image:\n", "  tag: \"0.9\"\n"]);
+                assert_eq!(conflict.theirs, vec!["image:\n", "  tag: \"2.0\"\n"]);
+            }
+            chunk => panic!("Expected a conflict, got {:?}", chunk),
+        }
+        assert_eq!(render(&chunks), MERGED_TEXT);
+    }
+
+    #[test]
+    fn ignores_lines_that_only_start_like_markers() {
+        let chunks = parse_merged_text("a: <<<<<<<\n<<<<<<<<\n=======x\n");
+        assert!(matches!(chunks.as_slice(), [Chunk::Common(lines)] if lines.len() == 3));
+    }
+
+    #[test]
+    fn describes_conflicts_by_their_innermost_keys() {
+        let conflict_descriptions = describe_conflicts(&parse_merged_text(MERGED_TEXT));
+        assert_eq!(conflict_descriptions.len(), 1);
+        assert_eq!(conflict_descriptions[0].key_paths, vec![vec![String::from("image"), String::from("tag")]]);
+    }
+
+    #[test]
+    fn resolves_conflicts_that_include_the_parent_key_line() {
+        let mut chunks = parse_merged_text(MERGED_TEXT);
+        let auto_resolutions = resolve_conflicts(&mut chunks, &[merge_policy("image.tag", Resolution::Theirs)], &Value::Null, &Value::Null);
+        assert_eq!(auto_resolutions.len(), 1);
+        assert_eq!(auto_resolutions[0].side, Side::Theirs);
+        assert_eq!(render(&chunks), "replicas: 1\nimage:\n  tag: \"2.0\"\nservice:\n  port: 80\n");
+    }
+
+    #[test]
+    fn leaves_conflicts_with_unmatched_keys() {
+        let merged_text = "<<<<<<< ours\nimage:\n  tag: \"1.0\"\n=======\nimage:\n  repository: nginx\n  tag: \"2.0\"\n>>>>>>> theirs\n";
+        let mut chunks = parse_merged_text(merged_text);
+        assert!(resolve_conflicts(&mut chunks, &[merge_policy("image.tag", Resolution::Ours)], &Value::Null, &Value::Null).is_empty());
+        assert_eq!(render(&chunks), merged_text);
+    }
+
+    #[test]
+    fn attributes_conflicts_in_block_scalars_to_their_key() {
+        let merged_text = "config: |\n<<<<<<< ours\n  tag: a\n=======\n  tag: b\n>>>>>>> theirs\nimage:\n  tag: \"1.0\"\n";
+        let mut chunks = parse_merged_text(merged_text);
+        assert_eq!(describe_conflicts(&chunks)[0].key_paths, vec![vec![String::from("config")]]);
+        assert!(resolve_conflicts(&mut chunks, &[merge_policy("*.tag", Resolution::Theirs)], &Value::Null, &Value::Null).is_empty());
+    }
+}
@@ -0,0 +1,392 @@
+use std::fs;
+use std::path::Path;
+use serde::Serialize;
+use serde_yaml::Value;
+use anyhow::Result;
+use log::{debug, warn};
+
+use crate::chart_diff;
+use crate::subcharts;
+use crate::values::{self, KeyPath, ValueChange};
+use crate::yaml_text;
+
+// CustomResourceDefinitions are the riskiest part of a chart update: helm installs the ones in `crds/` but never
+// upgrades or deletes them, and a changed schema can reject the custom resources that already exist. They are found
+// in the `crds/` dirs and the templates of a chart and its subcharts, packaged or not. Templates aren't YAML until helm
+// renders them, so template lines are left out to read them, and CRDs that still don't parse are compared by their text.
+
+const CRD_KIND: &str = "CustomResourceDefinition";
+
+// Long schema diffs are in the chart diff
+const MAX_SCHEMA_CHANGES: usize = 20;
+
+/// A CRD as found in a chart version.
+#[derive(Debug)]
+pub struct Crd {
+    pub name: String,
+    // The file it was found in, relative to the chart dir
+    pub source: String,
+    pub versions: Vec<String>,
+    // The openAPIV3Schema of every version, or of all versions in older CRDs
+    schemas: Vec<(String, Value)>,
+    // Only for CRDs that couldn't be parsed
+    text: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CrdChange {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// How a CRD differs between two chart versions.
+#[derive(Debug)]
+pub struct CrdDiff {
+    pub name: String,
+    pub change: CrdChange,
+    pub source: String,
+    pub original_versions: Vec<String>,
+    pub latest_versions: Vec<String>,
+    // Like `v1: removed spec.replicas`
+    pub schema_changes: Vec<String>,
+}
+
+/// Whether a file, relative to the chart dir, can hold CRDs: the files in `crds/` and `templates/` dirs, of the chart
+/// or of its subcharts, and the archives of the packaged subcharts.
+pub fn is_crd_source(path: &Path) -> bool {
+    path.iter().any(|component| component == "crds" || component == "templates") || is_packaged_subchart(path)
+}
+
+fn is_packaged_subchart(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "tgz") && path.parent().and_then(Path::file_name).is_some_and(|dir_name| dir_name == "charts")
+}
+
+/// Finds the CRDs in the extracted chart dir `chart_dir_path`, extracting its packaged subcharts next to them. A CRD that
+/// is both in a `crds/` dir and in a template, as charts do to let the user choose, is only counted once, from `crds/`.
+pub fn find_crds(chart_dir_path: &Path) -> Result<Vec<Crd>> {
+    let mut crds: Vec<Crd> = Vec::new();
+    for crd in find_all_crds(chart_dir_path, 0)? {
+        match crds.iter_mut().find(|known_crd| known_crd.name == crd.name) {
+            Some(known_crd) => {
+                debug!("CRD `{}` is both in `{}` and in `{}`.", crd.name, known_crd.source, crd.source);
+                if is_in_crds_dir(&crd.source) && !is_in_crds_dir(&known_crd.source) {
+                    *known_crd = crd;
+                }
+            }
+            None => crds.push(crd),
+        }
+    }
+
+    debug!("Found {} CRD(s) in `{}`.", crds.len(), chart_dir_path.display());
+    Ok(crds)
+}
+
+/// Whether the CRD at `source` is in a `crds/` dir, which helm installs but never upgrades.
+pub fn is_in_crds_dir(source: &str) -> bool {
+    source.split('/').any(|component| component == "crds")
+}
+
+fn find_all_crds(chart_dir_path: &Path, depth: usize) -> Result<Vec<Crd>> {
+    let mut crds = Vec::new();
+    for file_path in chart_diff::list_files(chart_dir_path)? {
+        let source = file_path.iter().map(|component| component.to_string_lossy()).collect::<Vec<_>>().join("/");
+
+        if let Some(archive_name) = file_path.file_name().and_then(|file_name| file_name.to_str()).and_then(|file_name| file_name.strip_suffix(".tgz")) {
+            if !is_packaged_subchart(&file_path) || depth >= subcharts::MAX_DEPTH {
+                continue;
+            }
+            let subchart_dir_path = match subcharts::extract_packaged_subchart(&chart_dir_path.join(&file_path), archive_name, is_crd_source) {
+                Ok(subchart_dir_path) => subchart_dir_path,
+                Err(e) => {
+                    warn!("Couldn't extract subchart archive `{}`, so its CRDs are ignored! {:#}", source, e);
+                    continue;
+                }
+            };
+            for mut crd in find_all_crds(&subchart_dir_path, depth + 1)? {
+                crd.source = format!("{}/{}", source, crd.source);
+                crds.push(crd);
+            }
+            continue;
+        }
+
+        let is_manifest = file_path.extension().is_some_and(|extension| extension == "yaml" || extension == "yml" || extension == "json");
+        if !is_manifest || !is_crd_source(&file_path) {
+            continue;
+        }
+        let text = match fs::read_to_string(chart_dir_path.join(&file_path)) {
+            Ok(text) if text.contains(CRD_KIND) => text,
+            _ => continue,
+        };
+
+        for document in split_documents(&text).iter().filter(|document| document.contains(CRD_KIND)) {
+            match parse_crd(document, &source) {
+                Some(crd) => crds.push(crd),
+                None => debug!("A document in `{}` mentions a {} but isn't one.", source, CRD_KIND),
+            }
+        }
+    }
+
+    Ok(crds)
+}
+
+fn split_documents(text: &str) -> Vec<String> {
+    let mut documents = vec![String::new()];
+    for line in text.lines() {
+        if yaml_text::is_document_marker(line) {
+            documents.push(String::new());
+        } else if let Some(document) = documents.last_mut() {
+            document.push_str(line);
+            document.push('\n');
+        }
+    }
+    documents
+}
+
+fn parse_crd(document: &str, source: &str) -> Option<Crd> {
+    // Lines that are only template actions, like `{{- if .Values.crds.enabled }}`, can't be parsed
+    let untemplated_document: String = document.lines()
+        .filter(|line| !(line.trim_start().starts_with("{{") && line.trim_end().ends_with("}}")))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    let parsed_crd = serde_yaml::from_str::<Value>(document).ok()
+        .or_else(|| serde_yaml::from_str::<Value>(&untemplated_document).ok())
+        .filter(|crd| crd.get("kind").and_then(Value::as_str) == Some(CRD_KIND));
+
+    let crd = match parsed_crd {
+        Some(crd) => crd,
+        None => {
+            if !document.lines().any(|line| line.trim() == format!("kind: {}", CRD_KIND)) {
+                return None;
+            }
+            warn!("Couldn't parse a {} in `{}`, so it is compared by its text.", CRD_KIND, source);
+            return Some(Crd { name: find_name(document)?, source: String::from(source), versions: Vec::new(), schemas: Vec::new(), text: Some(String::from(document)) });
+        }
+    };
+
+    let spec = crd.get("spec");
+    let name = crd.get("metadata").and_then(|metadata| metadata.get("name")).and_then(Value::as_str).map(String::from)
+        .or_else(|| {
+            let group = spec?.get("group")?.as_str()?;
+            let plural = spec?.get("names")?.get("plural")?.as_str()?;
+            Some(format!("{}.{}", plural, group))
+        })?;
+
+    let mut versions = Vec::new();
+    let mut schemas = Vec::new();
+    for version in spec.and_then(|spec| spec.get("versions")).and_then(Value::as_sequence).into_iter().flatten() {
+        let version_name = match version.get("name").and_then(Value::as_str) {
+            Some(version_name) => String::from(version_name),
+            None => continue,
+        };
+        if let Some(schema) = version.get("schema").and_then(|schema| schema.get("openAPIV3Schema")) {
+            schemas.push((version_name.clone(), schema.clone()));
+        }
+        versions.push(version_name);
+    }
+    // CRDs of `apiextensions.k8s.io/v1beta1` can have a single version and a schema for all versions
+    if let Some(version_name) = spec.and_then(|spec| spec.get("version")).and_then(Value::as_str) {
+        if !versions.iter().any(|known_version| known_version == version_name) {
+            versions.push(String::from(version_name));
+        }
+    }
+    if let Some(schema) = spec.and_then(|spec| spec.get("validation")).and_then(|validation| validation.get("openAPIV3Schema")) {
+        schemas.push((String::from("all versions"), schema.clone()));
+    }
+
+    Some(Crd { name, source: String::from(source), versions, schemas, text: None })
+}
+
+// The `name` right under `metadata`, read from the text
+fn find_name(document: &str) -> Option<String> {
+    let lines: Vec<&str> = document.lines().collect();
+    let metadata_line = lines.iter().position(|line| line.trim_end() == "metadata:")?;
+    lines[metadata_line + 1..].iter()
+        .take_while(|line| line.starts_with(' ') || line.trim().is_empty())
+        .find_map(|line| line.trim().strip_prefix("name:"))
+        .map(|name| name.trim().trim_matches(['"', '\'']).to_string())
+}
+
+/// Compares the CRDs of two chart versions by name.
+pub fn diff_crds(original_crds: &[Crd], latest_crds: &[Crd]) -> Vec<CrdDiff> {
+    let mut crd_diffs = Vec::new();
+    for latest_crd in latest_crds {
+        let original_crd = match original_crds.iter().find(|original_crd| original_crd.name == latest_crd.name) {
+            Some(original_crd) => original_crd,
+            None => {
+                crd_diffs.push(CrdDiff {
+                    name: latest_crd.name.clone(),
+                    change: CrdChange::Added,
+                    source: latest_crd.source.clone(),
+                    original_versions: Vec::new(),
+                    latest_versions: latest_crd.versions.clone(),
+                    schema_changes: Vec::new(),
+                });
+                continue;
+            }
+        };
+
+        let schema_changes = diff_schemas(original_crd, latest_crd);
+        if original_crd.versions != latest_crd.versions || !schema_changes.is_empty() {
+            crd_diffs.push(CrdDiff {
+                name: latest_crd.name.clone(),
+                change: CrdChange::Changed,
+                source: latest_crd.source.clone(),
+                original_versions: original_crd.versions.clone(),
+                latest_versions: latest_crd.versions.clone(),
+                schema_changes,
+            });
+        }
+    }
+    for original_crd in original_crds.iter().filter(|original_crd| !latest_crds.iter().any(|latest_crd| latest_crd.name == original_crd.name)) {
+        crd_diffs.push(CrdDiff {
+            name: original_crd.name.clone(),
+            change: CrdChange::Removed,
+            source: original_crd.source.clone(),
+            original_versions: original_crd.versions.clone(),
+            latest_versions: Vec::new(),
+            schema_changes: Vec::new(),
+        });
+    }
+
+    crd_diffs
+}
+
+// Descriptions don't change what the API accepts, so they are left out
+fn diff_schemas(original_crd: &Crd, latest_crd: &Crd) -> Vec<String> {
+    if original_crd.text.is_some() || latest_crd.text.is_some() {
+        return if original_crd.text == latest_crd.text { Vec::new() } else { vec![String::from("the definition changed, but couldn't be parsed")] };
+    }
+
+    let mut schema_changes = Vec::new();
+    for (version, latest_schema) in &latest_crd.schemas {
+        let original_schema = match original_crd.schemas.iter().find(|(original_version, _)| original_version == version) {
+            Some((_, original_schema)) => original_schema,
+            None => continue,
+        };
+        for (key_path, value_change) in values::diff_values(original_schema, latest_schema) {
+            if key_path.last().is_some_and(|key| key == "description") {
+                continue;
+            }
+            let change = match value_change {
+                ValueChange::Added(_) => "added",
+                ValueChange::Removed(_) => "removed",
+                ValueChange::Changed(_, _) => "changed",
+            };
+            schema_changes.push(format!("{}: {} `{}`", version, change, format_schema_key_path(&key_path)));
+        }
+    }
+
+    if schema_changes.len() > MAX_SCHEMA_CHANGES {
+        let more_count = schema_changes.len() - MAX_SCHEMA_CHANGES;
+        schema_changes.truncate(MAX_SCHEMA_CHANGES);
+        schema_changes.push(format!("... and {} more schema changes", more_count));
+    }
+    schema_changes
+}
+
+// `properties.spec.properties.replicas.maximum` is `spec.replicas.maximum` in the custom resources
+fn format_schema_key_path(key_path: &KeyPath) -> String {
+    let key_path: KeyPath = key_path.iter().filter(|key| *key != "properties").cloned().collect();
+    if key_path.is_empty() { String::from("(root)") } else { values::format_key_path(&key_path) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use tar::{Builder, Header};
+    use tempfile::TempDir;
+
+    const CRD: &str = "apiVersion: apiextensions.k8s.io/v1\nkind: CustomResourceDefinition\nmetadata:\n  name: widgets.example.com\nspec:\n  group: example.com\n  names:\n    plural: widgets\n  versions:\n    - name: v1\n      schema:\n        openAPIV3Schema:\n          type: object\n          properties:\n            spec:\n              type: object\n              properties:\n                replicas:\n                  type: integer\n    - name: v1beta1\n";
+
+    fn write_file(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn parses_crds() {
+        let crd = parse_crd(CRD, "crds/widgets.yaml").unwrap();
+        assert_eq!(crd.name, "widgets.example.com");
+        assert_eq!(crd.versions, vec!["v1", "v1beta1"]);
+        assert_eq!(crd.schemas.len(), 1);
+        assert!(crd.text.is_none());
+    }
+
+    #[test]
+    fn parses_v1beta1_crds() {
+        let document = "apiVersion: apiextensions.k8s.io/v1beta1\nkind: CustomResourceDefinition\nspec:\n  group: example.com\n  names:\n    plural: gadgets\n  version: v1alpha1\n  validation:\n    openAPIV3Schema:\n      type: object\n";
+        let crd = parse_crd(document, "crds/gadgets.yaml").unwrap();
+        assert_eq!(crd.name, "gadgets.example.com");
+        assert_eq!(crd.versions, vec!["v1alpha1"]);
+        assert_eq!(crd.schemas[0].0, "all versions");
+    }
+
+    #[test]
+    fn parses_templated_crds() {
+        let templated_crd = format!("{{{{- if .Values.crds.enabled }}}}\n{}{{{{- end }}}}\n", CRD);
+        assert_eq!(parse_crd(&templated_crd, "templates/crds.yaml").unwrap().versions, vec!["v1", "v1beta1"]);
+
+        let unparseable_crd = "kind: CustomResourceDefinition\nmetadata:\n  name: {{ .Values.name }}.example.com\n  labels: {{ include \"labels\" . }}\n";
+        let crd = parse_crd(unparseable_crd, "templates/crds.yaml").unwrap();
+        assert_eq!(crd.name, "{{ .Values.name }}.example.com");
+        assert!(crd.text.is_some());
+    }
+
+    #[test]
+    fn ignores_other_kinds() {
+        assert!(parse_crd("kind: ConfigMap\ndata:\n  kind: CustomResourceDefinition\n", "templates/cm.yaml").is_none());
+    }
+
+    #[test]
+    fn finds_crds_in_packaged_subcharts() {
+        let dir = TempDir::new().unwrap();
+        let chart_dir_path = dir.path().join("mychart");
+        fs::create_dir_all(chart_dir_path.join("charts")).unwrap();
+        let mut builder = Builder::new(GzEncoder::new(File::create(chart_dir_path.join("charts/operator-1.0.0.tgz")).unwrap(), Compression::fast()));
+        for (path, content) in [("operator/Chart.yaml", "name: operator\n"), ("operator/crds/widgets.yaml", CRD)] {
+            let mut header = Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, content.as_bytes()).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let crds = find_crds(&chart_dir_path).unwrap();
+        assert_eq!(crds.len(), 1);
+        assert_eq!(crds[0].source, "charts/operator-1.0.0.tgz/crds/widgets.yaml");
+    }
+
+    #[test]
+    fn counts_crds_in_crds_and_templates_once() {
+        let dir = TempDir::new().unwrap();
+        write_file(&dir.path().join("templates/widgets.yaml"), CRD);
+        write_file(&dir.path().join("crds/widgets.yaml"), CRD);
+
+        let crds = find_crds(dir.path()).unwrap();
+        assert_eq!(crds.len(), 1);
+        assert_eq!(crds[0].source, "crds/widgets.yaml");
+    }
+
+    #[test]
+    fn diffs_crds_by_name() {
+        let original_crds = vec![parse_crd(CRD, "crds/widgets.yaml").unwrap()];
+        let changed_crd = CRD.replace("type: integer", "type: string").replace("    - name: v1beta1\n", "");
+        let latest_crds = vec![parse_crd(&changed_crd, "crds/widgets.yaml").unwrap()];
+
+        let crd_diffs = diff_crds(&original_crds, &latest_crds);
+        assert_eq!(crd_diffs.len(), 1);
+        assert_eq!(crd_diffs[0].change, CrdChange::Changed);
+        assert_eq!(crd_diffs[0].latest_versions, vec!["v1"]);
+        assert_eq!(crd_diffs[0].schema_changes, vec!["v1: changed `spec.replicas.type`"]);
+
+        assert_eq!(diff_crds(&original_crds, &[])[0].change, CrdChange::Removed);
+        assert_eq!(diff_crds(&[], &original_crds)[0].change, CrdChange::Added);
+        assert!(diff_crds(&original_crds, &original_crds).is_empty());
+    }
+}
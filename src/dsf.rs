@@ -0,0 +1,110 @@
+use std::fs;
+use std::path::Path;
+use serde_yaml::Value;
+use toml_edit::DocumentMut;
+use anyhow::{Context, Result};
+use log::debug;
+
+// Helmsman DSFs can be written in YAML or in TOML. TOML DSFs are read into the same `Value` as YAML ones,
+// so the rest of hmum doesn't have to care, and are edited with `toml_edit` to keep their formatting and comments.
+
+pub fn is_toml(dsf_path: &Path) -> bool {
+    dsf_path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("toml"))
+}
+
+pub fn parse_toml_file(dsf_path: &Path) -> Result<Value> {
+    debug!("Attempting to parse toml file `{}`.", dsf_path.display());
+
+    let dsf_content = fs::read_to_string(dsf_path)
+        .with_context(|| format!("Could not open file `{}`", dsf_path.display()))?;
+    let dsf_value: Value = toml_edit::de::from_str(&dsf_content)
+        .with_context(|| "Could not parse toml file!")?;
+
+    debug!("File `{}` was parsed successfully!", dsf_path.display());
+    Ok(dsf_value)
+}
+
+/// Sets the `version` of app `app_name` in the TOML DSF to `latest_app_version`, leaving the rest of the file as it is.
+pub fn update_toml_version(dsf_path: &Path, app_name: &str, current_app_version: &str, latest_app_version: &str) -> Result<()> {
+    debug!("Attempting to update the version for chart `{}` in helmsman DSF `{}` to `{}`.", app_name, dsf_path.display(), latest_app_version);
+
+    let dsf_content = fs::read_to_string(dsf_path)
+        .with_context(|| format!("Could not open file `{}`", dsf_path.display()))?;
+    let mut dsf_document: DocumentMut = dsf_content.parse()
+        .with_context(|| format!("Could not parse helmsman DSF `{}`!", dsf_path.display()))?;
+
+    let version_value = dsf_document.get_mut("apps")
+        .and_then(|apps| apps.get_mut(app_name))
+        .and_then(|app| app.get_mut("version"))
+        .and_then(|version| version.as_value_mut())
+        .filter(|version| version.as_str() == Some(current_app_version))
+        .with_context(|| format!("Couldn't find the version to update in the helmsman DSF `{}`! Did the file change in the meantime?", dsf_path.display()))?;
+
+    let decor = version_value.decor().clone();
+    *version_value = toml_edit::Value::from(latest_app_version);
+    *version_value.decor_mut() = decor;
+
+    fs::write(dsf_path, dsf_document.to_string())
+        .with_context(|| format!("Failed to write to the helmsman DSF `{}` to update version!", dsf_path.display()))?;
+
+    debug!("Version for chart `{}` was updated successfully in helmsman DSF `{}`.", app_name, dsf_path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const TOML_DSF: &str = r#"# Our apps
+[helmRepos]
+stable = "https://charts.example.com"
+
+[apps]
+
+  [apps.web]
+  chart = "stable/web"
+  version = "1.0.0" # pinned until the migration
+  valuesFiles = ["web.yaml", "web-prod.yaml"]
+  priority = -2
+
+  [apps.db]
+  chart = "stable/db"
+  version = "1.0.0"
+"#;
+
+    fn write_dsf(dir: &TempDir) -> std::path::PathBuf {
+        let dsf_path = dir.path().join("dsf.toml");
+        fs::write(&dsf_path, TOML_DSF).unwrap();
+        dsf_path
+    }
+
+    #[test]
+    fn parses_toml_like_the_yaml_dsf() {
+        let dir = TempDir::new().unwrap();
+        let yaml_dsf: Value = serde_yaml::from_str(concat!(
+            "helmRepos:\n  stable: https://charts.example.com\n",
+            "apps:\n",
+            "  web:\n    chart: stable/web\n    version: 1.0.0\n    valuesFiles: [web.yaml, web-prod.yaml]\n    priority: -2\n",
+            "  db:\n    chart: stable/db\n    version: 1.0.0\n",
+        )).unwrap();
+        assert_eq!(parse_toml_file(&write_dsf(&dir)).unwrap(), yaml_dsf);
+    }
+
+    #[test]
+    fn updates_only_the_apps_version() {
+        let dir = TempDir::new().unwrap();
+        let dsf_path = write_dsf(&dir);
+        update_toml_version(&dsf_path, "web", "1.0.0", "1.2.0").unwrap();
+        assert_eq!(fs::read_to_string(&dsf_path).unwrap(), TOML_DSF.replacen("version = \"1.0.0\" #", "version = \"1.2.0\" #", 1));
+    }
+
+    #[test]
+    fn refuses_to_update_a_version_that_changed() {
+        let dir = TempDir::new().unwrap();
+        let dsf_path = write_dsf(&dir);
+        assert!(update_toml_version(&dsf_path, "web", "0.9.0", "1.2.0").is_err());
+        assert!(update_toml_version(&dsf_path, "api", "1.0.0", "1.2.0").is_err());
+        assert_eq!(fs::read_to_string(&dsf_path).unwrap(), TOML_DSF);
+    }
+}
@@ -0,0 +1,94 @@
+use std::env;
+use regex::{Captures, Regex};
+use anyhow::Result;
+use log::trace;
+
+// Helmsman expands `$VAR` and `${VAR}` in DSF values, so hmum has to do the same before it uses them.
+// Only the values hmum works with are expanded, the DSF itself is always rewritten from its raw text.
+// `$$` is a literal `$`. Shell forms like `${VAR:-default}` aren't supported and fail instead of being left as they are.
+
+fn env_var_regex() -> Regex {
+    Regex::new(r"(?P<escape>\$\$)|\$\{(?P<braced>[A-Za-z_][A-Za-z0-9_]*)\}|\$(?P<plain>[A-Za-z_][A-Za-z0-9_]*)|(?P<unsupported>\$\{[^}]*\}?)").unwrap()
+}
+
+/// Whether `value` references environment variables, `$$` being a literal `$` rather than a reference.
+pub fn has_env_vars(value: &str) -> bool {
+    env_var_regex().captures_iter(value)
+        .any(|captures| captures.name("escape").is_none())
+}
+
+/// Replaces the `$VAR` and `${VAR}` references in `value` with the values of the environment variables and `$$`
+/// with `$`. Like helmsman, fails when a referenced variable is not set.
+pub fn expand_env_vars(value: &str) -> Result<String> {
+    let regex = env_var_regex();
+
+    let unsupported_refs: Vec<&str> = regex.captures_iter(value)
+        .filter_map(|captures| captures.name("unsupported"))
+        .map(|unsupported_ref| unsupported_ref.as_str())
+        .collect();
+    if !unsupported_refs.is_empty() {
+        return Err(anyhow::anyhow!("Environment variable reference(s) `{}` used in `{}` are not supported, only `$VAR` and `${{VAR}}` are!", unsupported_refs.join("`, `"), value));
+    }
+
+    let missing_vars: Vec<&str> = regex.captures_iter(value)
+        .filter_map(|captures| var_name(&captures))
+        .filter(|var_name| env::var_os(var_name).is_none())
+        .collect();
+    if !missing_vars.is_empty() {
+        return Err(anyhow::anyhow!("Environment variable(s) `{}` used in `{}` are not set!", missing_vars.join("`, `"), value));
+    }
+
+    let expanded_value = regex.replace_all(value, |captures: &Captures| match var_name(captures) {
+        Some(var_name) => env::var(var_name).unwrap_or_default(),
+        None => String::from("$"),
+    });
+    if expanded_value != value {
+        trace!("Expanded environment variables in `{}`.", value);
+    }
+
+    Ok(expanded_value.into_owned())
+}
+
+// `None` for `$$`
+fn var_name<'t>(captures: &Captures<'t>) -> Option<&'t str> {
+    captures.name("braced").or_else(|| captures.name("plain")).map(|var_name| var_name.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_plain_and_braced_vars() {
+        env::set_var("HMUM_TEST_HOST", "charts.example.com");
+        assert_eq!(expand_env_vars("https://$HMUM_TEST_HOST/${HMUM_TEST_HOST}x").unwrap(), "https://charts.example.com/charts.example.comx");
+    }
+
+    #[test]
+    fn leaves_values_without_vars_as_they_are() {
+        assert_eq!(expand_env_vars("1.2.3").unwrap(), "1.2.3");
+        assert!(!has_env_vars("1.2.3"));
+    }
+
+    #[test]
+    fn turns_double_dollars_into_dollars() {
+        env::set_var("HMUM_TEST_ESCAPED", "not this");
+        assert_eq!(expand_env_vars("pa$$word-$$HMUM_TEST_ESCAPED").unwrap(), "pa$word-$HMUM_TEST_ESCAPED");
+        assert!(!has_env_vars("pa$$word-$$HMUM_TEST_ESCAPED"));
+        assert!(has_env_vars("pa$$word-$HMUM_TEST_ESCAPED"));
+        assert!(has_env_vars("${HMUM_TEST_ESCAPED}"));
+    }
+
+    #[test]
+    fn fails_for_unset_vars() {
+        let error = expand_env_vars("$HMUM_TEST_UNSET/${HMUM_TEST_UNSET_TOO}").unwrap_err().to_string();
+        assert!(error.contains("`HMUM_TEST_UNSET`, `HMUM_TEST_UNSET_TOO`"));
+    }
+
+    #[test]
+    fn fails_for_defaults() {
+        env::set_var("HMUM_TEST_WITH_DEFAULT", "set");
+        assert!(expand_env_vars("${HMUM_TEST_WITH_DEFAULT:-default}").unwrap_err().to_string().contains("not supported"));
+        assert!(has_env_vars("${HMUM_TEST_WITH_DEFAULT:-default}"));
+    }
+}
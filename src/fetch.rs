@@ -0,0 +1,336 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, copy};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+use url::Url;
+use anyhow::{Context, Result};
+use rand::{thread_rng, Rng};
+use rand::distributions::Alphanumeric;
+use sha2::{Digest, Sha256};
+use log::{info, debug, warn};
+
+use crate::helm;
+
+#[derive(Clone)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+// Keep the password out of the debug/trace logs
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username)
+            .field("password", &"***")
+            .finish()
+    }
+}
+
+// A URL to download a file from, with the credentials to send along, if any
+#[derive(Debug)]
+pub struct Source<'c> {
+    pub url: Url,
+    pub credentials: Option<&'c Credentials>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DownloadSettings {
+    pub connect_timeout: Duration,
+    pub read_timeout: Duration,
+    pub retries: u32,
+}
+
+const RETRY_BACKOFF_BASE: Duration = Duration::from_millis(500);
+const RETRY_BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+// Downloads indexes and chart archives. Everything downloaded is also kept in the hmum cache, so a later run
+// with `--offline` can resolve it without network access.
+#[derive(Debug)]
+pub struct Fetcher<'a> {
+    tmp_dir: &'a TempDir,
+    cache_dir: PathBuf,
+    offline: bool,
+    download_settings: DownloadSettings,
+}
+
+impl<'a> Fetcher<'a> {
+    pub fn new(tmp_dir: &'a TempDir, cache_dir: PathBuf, offline: bool, download_settings: DownloadSettings) -> Fetcher<'a> {
+        Fetcher { tmp_dir, cache_dir, offline, download_settings }
+    }
+
+    pub fn tmp_dir(&self) -> &TempDir {
+        self.tmp_dir
+    }
+
+    /// Retrieves the index.yaml of a repo from the first of `index_sources` that works. The first source is the
+    /// repo's own one, the others are its mirrors.
+    pub fn fetch_index(&self, repo_name: &str, repo_url: &Url, index_sources: &[Source]) -> Result<PathBuf> {
+        let cached_index_path = self.cache_dir.join("repository").join(format!("{}-index.yaml", cache_key(repo_url.as_str())));
+
+        if self.offline {
+            if cached_index_path.exists() {
+                debug!("Using index.yaml for repo `{}` from the hmum cache `{}`.", repo_name, cached_index_path.display());
+                return Ok(cached_index_path);
+            }
+
+            if let Some(helm_index_path) = helm::find_cached_index(repo_url)? {
+                debug!("Using index.yaml for repo `{}` from Helm's repository cache `{}`.", repo_name, helm_index_path.display());
+                return Ok(helm_index_path);
+            }
+
+            return Err(anyhow::anyhow!("Running offline and the index.yaml for repo `{}` is neither in the hmum cache nor in Helm's repository cache! Run `helm repo add {} {}` or run hmum online once.",
+                                       repo_name, repo_name, repo_url));
+        }
+
+        let (index_file_path, _) = self.download_from_sources(index_sources)
+            .with_context(|| format!("Failed to download `index.yaml` file for repo `{}`!", repo_name))?;
+        self.store_in_cache(&index_file_path, &cached_index_path);
+
+        Ok(index_file_path)
+    }
+
+    /// Retrieves a chart archive from the first of `chart_sources` that works and returns it along with the URL it came from.
+    /// `repo_url` is the url of the repo the chart is from.
+    pub fn fetch_chart_archive(&self, repo_url: &Url, chart_name: &str, chart_version: &str, chart_sources: &[Source]) -> Result<(PathBuf, Url)> {
+        self.fetch_chart_file(repo_url, chart_name, chart_version, chart_sources, "tgz")
+    }
+
+    pub fn fetch_chart_provenance(&self, repo_url: &Url, chart_name: &str, chart_version: &str, provenance_sources: &[Source]) -> Result<(PathBuf, Url)> {
+        self.fetch_chart_file(repo_url, chart_name, chart_version, provenance_sources, "tgz.prov")
+    }
+
+    fn fetch_chart_file(&self, repo_url: &Url, chart_name: &str, chart_version: &str, chart_file_sources: &[Source], extension: &str) -> Result<(PathBuf, Url)> {
+        let chart_file_url = &chart_file_sources.first()
+            .with_context(|| format!("There is no url to download `{}-{}.{}` from!", chart_name, chart_version, extension))?
+            .url;
+        let cached_chart_file_path = |url: &Url| self.cache_dir.join("charts").join(cache_key(url.as_str()));
+
+        if self.offline {
+            if let Some(source) = chart_file_sources.iter().find(|source| cached_chart_file_path(&source.url).exists()) {
+                debug!("Using `{}` from the hmum cache `{}`.", source.url, cached_chart_file_path(&source.url).display());
+                return Ok((cached_chart_file_path(&source.url), source.url.clone()));
+            }
+
+            if let Some(helm_chart_file_path) = helm::find_cached_chart_file(repo_url, chart_name, chart_version, extension)? {
+                debug!("Using `{}` from Helm's repository cache `{}`.", chart_file_url, helm_chart_file_path.display());
+                return Ok((helm_chart_file_path, chart_file_url.clone()));
+            }
+
+            return Err(anyhow::anyhow!("Running offline and `{}-{}.{}` of chart `{}` (`{}`) is neither in the hmum cache nor in Helm's repository cache! Run `helm pull` for it or run hmum online once.",
+                                       chart_name, chart_version, extension, chart_name, chart_file_url));
+        }
+
+        let (chart_file_path, used_url) = self.download_from_sources(chart_file_sources)?;
+        self.store_in_cache(&chart_file_path, &cached_chart_file_path(&used_url));
+
+        Ok((chart_file_path, used_url))
+    }
+
+    fn download_from_sources(&self, sources: &[Source]) -> Result<(PathBuf, Url)> {
+        let mut failures = Vec::new();
+
+        for source in sources {
+            match self.download_with_retries(source) {
+                Ok(file_path) => return Ok((file_path, source.url.clone())),
+                Err(e) => {
+                    if sources.len() > 1 {
+                        warn!("Downloading `{}` failed, will try the next url if there is one: {:#}", source.url, e);
+                    }
+                    failures.push(format!("`{}`: {:#}", source.url, e));
+                }
+            }
+        }
+
+        Err(anyhow::anyhow!("Couldn't download the file from any of its urls! {}", failures.join(", ")))
+    }
+
+    fn download_with_retries(&self, source: &Source) -> Result<PathBuf> {
+        let mut attempt = 0;
+
+        loop {
+            match download_file_to_temp(self.tmp_dir, source, &self.download_settings) {
+                Ok(file_path) => return Ok(file_path),
+                Err(DownloadError::Transient(e)) if attempt < self.download_settings.retries => {
+                    let backoff = retry_backoff(attempt);
+                    attempt += 1;
+                    info!("Downloading `{}` failed, retrying in {:?} ({}/{}): {:#}", source.url, backoff, attempt, self.download_settings.retries, e);
+                    thread::sleep(backoff);
+                }
+                Err(DownloadError::Transient(e)) | Err(DownloadError::Permanent(e)) => return Err(e),
+            }
+        }
+    }
+
+    // A broken cache should never fail an online run, so failing to store something is only a warning.
+    fn store_in_cache(&self, file_path: &Path, cached_file_path: &Path) {
+        let store_result = cached_file_path.parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::copy(file_path, cached_file_path));
+
+        match store_result {
+            Ok(_) => debug!("Stored `{}` in the hmum cache as `{}`.", file_path.display(), cached_file_path.display()),
+            Err(e) => warn!("Couldn't store `{}` in the hmum cache as `{}`: {}", file_path.display(), cached_file_path.display(), e),
+        }
+    }
+}
+
+/// Runs `job` for every input on at most `concurrency` threads and returns the outputs in the order of the inputs.
+pub fn run_concurrently<I, O, F>(inputs: Vec<I>, concurrency: usize, job: F) -> Vec<O>
+    where I: Send, O: Send, F: Fn(I) -> O + Sync {
+    let inputs_count = inputs.len();
+    let pending_inputs = Mutex::new(inputs.into_iter().enumerate());
+    let outputs: Mutex<Vec<Option<O>>> = Mutex::new((0..inputs_count).map(|_| None).collect());
+
+    thread::scope(|scope| {
+        for _ in 0..concurrency.clamp(1, inputs_count.max(1)) {
+            scope.spawn(|| loop {
+                let next_input = pending_inputs.lock().unwrap().next();
+                match next_input {
+                    Some((index, input)) => {
+                        let output = job(input);
+                        outputs.lock().unwrap()[index] = Some(output);
+                    }
+                    None => break,
+                }
+            });
+        }
+    });
+
+    outputs.into_inner().unwrap().into_iter()
+        .map(|output| output.unwrap())
+        .collect()
+}
+
+pub fn default_cache_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|cache_dir| cache_dir.join("hmum"))
+}
+
+fn cache_key(url: &str) -> String {
+    url.trim_end_matches('/')
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+        .collect()
+}
+
+// Doubles with every attempt, up to a maximum
+fn retry_backoff(attempt: u32) -> Duration {
+    RETRY_BACKOFF_BASE.checked_mul(2u32.saturating_pow(attempt))
+        .map_or(RETRY_BACKOFF_MAX, |backoff| backoff.min(RETRY_BACKOFF_MAX))
+}
+
+// Timeouts, rate limiting and the server errors of overloaded or restarting servers
+fn is_transient_status(status: u16) -> bool {
+    matches!(status, 408 | 429 | 500 | 502 | 503 | 504)
+}
+
+// Whether trying again later could help
+enum DownloadError {
+    Transient(anyhow::Error),
+    Permanent(anyhow::Error),
+}
+
+fn download_file_to_temp(tmp_dir: &TempDir, source: &Source, download_settings: &DownloadSettings) -> Result<PathBuf, DownloadError> {
+    debug!("Attempting to download file from `{}` to temporary folder.", source.url);
+    let mut request = ureq::get(source.url.as_str());
+    request.timeout_connect(download_settings.connect_timeout.as_millis() as u64);
+    request.timeout_read(download_settings.read_timeout.as_millis() as u64);
+    if let Some(credentials) = source.credentials {
+        request.auth(&credentials.username, &credentials.password);
+    }
+    let response = request.call();
+    let temp_file_path = tmp_dir.path().join(generate_rand_filename());
+
+    if response.ok() {
+        let mut temp_file = File::create(&temp_file_path)
+            .with_context(|| format!("An error occurred while creating a tempfile in folder `{}`", tmp_dir.path().display()))
+            .map_err(DownloadError::Permanent)?;
+
+        // Connections that break or time out in the middle of the download are worth another try
+        copy(&mut response.into_reader(), &mut temp_file)
+            .with_context(|| format!("Reading the response from `{}` failed!", source.url))
+            .map_err(DownloadError::Transient)?;
+
+        debug!("File was downloaded successfully to `{}`.", temp_file_path.to_str().unwrap());
+        Ok(temp_file_path)
+    } else {
+        let error = anyhow::anyhow!("Fetching the file failed with `{}`!", &response.status_line());
+        let is_transient = match response.synthetic_error() {
+            Some(ureq::Error::ConnectionFailed(_)) | Some(ureq::Error::DnsFailed(_)) | Some(ureq::Error::BadStatusRead) | Some(ureq::Error::Io(_)) => true,
+            Some(_) => false,
+            None => is_transient_status(response.status()),
+        };
+
+        if is_transient {
+            Err(DownloadError::Transient(error))
+        } else {
+            Err(DownloadError::Permanent(error))
+        }
+    }
+}
+
+pub fn sha256_digest(file_path: &Path) -> Result<String> {
+    let mut file = File::open(file_path)
+        .with_context(|| format!("Couldn't open file `{}`!", file_path.display()))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("Couldn't compute the digest of file `{}`!", file_path.display()))?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+pub fn generate_rand_filename() -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(30)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retries_timeouts_rate_limits_and_server_errors() {
+        for status in [408, 429, 500, 502, 503, 504] {
+            assert!(is_transient_status(status), "{} should be retried", status);
+        }
+        for status in [400, 401, 403, 404, 410, 501] {
+            assert!(!is_transient_status(status), "{} shouldn't be retried", status);
+        }
+    }
+
+    #[test]
+    fn doubles_the_backoff_up_to_the_maximum() {
+        assert_eq!(retry_backoff(0), Duration::from_millis(500));
+        assert_eq!(retry_backoff(1), Duration::from_secs(1));
+        assert_eq!(retry_backoff(5), Duration::from_secs(16));
+        assert_eq!(retry_backoff(6), RETRY_BACKOFF_MAX);
+        assert_eq!(retry_backoff(u32::MAX), RETRY_BACKOFF_MAX);
+    }
+
+    #[test]
+    fn returns_the_outputs_in_the_order_of_the_inputs() {
+        // The first inputs take the longest, so they finish last
+        let outputs = run_concurrently((0..8u64).collect(), 4, |input| {
+            thread::sleep(Duration::from_millis(40 - input * 5));
+            input * 10
+        });
+        assert_eq!(outputs, vec![0, 10, 20, 30, 40, 50, 60, 70]);
+
+        assert_eq!(run_concurrently(vec![1, 2], 0, |input| input + 1), vec![2, 3]);
+        assert!(run_concurrently(Vec::<u32>::new(), 4, |input| input).is_empty());
+    }
+
+    #[test]
+    fn returns_the_errors_of_failed_jobs_with_their_input() {
+        let outputs = run_concurrently(vec!["1", "x", "3"], 2, |input| input.parse::<u32>().with_context(|| format!("`{}` isn't a number!", input)));
+        assert_eq!(outputs[0].as_ref().unwrap(), &1);
+        assert_eq!(outputs[1].as_ref().unwrap_err().to_string(), "`x` isn't a number!");
+        assert_eq!(outputs[2].as_ref().unwrap(), &3);
+    }
+}
@@ -0,0 +1,224 @@
+use std::env;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use serde_yaml::Value;
+use url::Url;
+use anyhow::{Context, Result};
+use log::{debug, trace};
+
+use crate::fetch::sha256_digest;
+
+// Lookups in the local Helm installation, the same way the helm CLI resolves them.
+
+#[derive(Debug, Deserialize)]
+pub struct HelmRepoEntry {
+    pub name: String,
+    pub url: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HelmRepoConfig {
+    #[serde(default)]
+    repositories: Option<Vec<HelmRepoEntry>>,
+}
+
+fn helm_config_home() -> Option<PathBuf> {
+    env::var_os("HELM_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| dirs::config_dir().map(|config_dir| config_dir.join("helm")))
+}
+
+fn helm_cache_home() -> Option<PathBuf> {
+    env::var_os("HELM_CACHE_HOME").map(PathBuf::from)
+        .or_else(|| dirs::cache_dir().map(|cache_dir| cache_dir.join("helm")))
+}
+
+pub fn repository_config_path() -> Option<PathBuf> {
+    env::var_os("HELM_REPOSITORY_CONFIG").map(PathBuf::from)
+        .or_else(|| helm_config_home().map(|config_home| config_home.join("repositories.yaml")))
+}
+
+pub fn repository_cache_path() -> Option<PathBuf> {
+    env::var_os("HELM_REPOSITORY_CACHE").map(PathBuf::from)
+        .or_else(|| helm_cache_home().map(|cache_home| cache_home.join("repository")))
+}
+
+pub fn read_repository_config() -> Result<Vec<HelmRepoEntry>> {
+    match repository_config_path() {
+        Some(repository_config_path) if repository_config_path.exists() => read_repository_config_file(&repository_config_path),
+        _ => {
+            debug!("Couldn't find Helm's repositories.yaml file.");
+            Ok(Vec::new())
+        }
+    }
+}
+
+fn read_repository_config_file(repository_config_path: &Path) -> Result<Vec<HelmRepoEntry>> {
+    let repository_config_path_str = repository_config_path.to_str().unwrap();
+
+    debug!("Attempting to read Helm's repositories file `{}`.", repository_config_path_str);
+    let file = File::open(repository_config_path)
+        .with_context(|| format!("Could not open file `{}`", repository_config_path_str))?;
+    let repository_config: HelmRepoConfig = serde_yaml::from_reader(BufReader::new(file))
+        .with_context(|| format!("Could not parse Helm's repositories file `{}`!", repository_config_path_str))?;
+
+    let repositories = repository_config.repositories.unwrap_or_default();
+    trace!("Read the following repos from Helm's repositories file: `{:?}`.", repositories.iter().map(|helm_repo| &helm_repo.name).collect::<Vec<_>>());
+    Ok(repositories)
+}
+
+pub fn find_repo(repo_name: &str) -> Result<Option<HelmRepoEntry>> {
+    Ok(read_repository_config()?.into_iter().find(|helm_repo| helm_repo.name == repo_name))
+}
+
+/// Finds the index.yaml that `helm repo add`/`helm repo update` cached for the repo with the given URL.
+pub fn find_cached_index(repo_url: &Url) -> Result<Option<PathBuf>> {
+    match repository_cache_path() {
+        Some(repository_cache_path) => Ok(find_index_in_cache(&read_repository_config()?, &repository_cache_path, repo_url)),
+        None => Ok(None),
+    }
+}
+
+fn find_index_in_cache(helm_repos: &[HelmRepoEntry], repository_cache_path: &Path, repo_url: &Url) -> Option<PathBuf> {
+    helm_repos.iter()
+        .find(|helm_repo| same_repo_url(&helm_repo.url, repo_url.as_str()))
+        .map(|helm_repo| repository_cache_path.join(format!("{}-index.yaml", helm_repo.name)))
+        .filter(|index_path| index_path.exists())
+}
+
+/// Finds a chart file that helm left in its repository cache, e.g. the archive after a `helm pull` or
+/// `helm dependency build`. `extension` is `tgz` for the archive and `tgz.prov` for its provenance file.
+/// The cache holds the charts of all repos side by side, so only versions that the cached index.yaml of the repo
+/// with `repo_url` lists are used, and archives only if their digest is the one listed there.
+pub fn find_cached_chart_file(repo_url: &Url, chart_name: &str, chart_version: &str, extension: &str) -> Result<Option<PathBuf>> {
+    match repository_cache_path() {
+        Some(repository_cache_path) => find_chart_file_in_cache(&read_repository_config()?, &repository_cache_path, repo_url, chart_name, chart_version, extension),
+        None => Ok(None),
+    }
+}
+
+fn find_chart_file_in_cache(helm_repos: &[HelmRepoEntry], repository_cache_path: &Path, repo_url: &Url, chart_name: &str, chart_version: &str, extension: &str) -> Result<Option<PathBuf>> {
+    let chart_file_path = repository_cache_path.join(format!("{}-{}.{}", chart_name, chart_version, extension));
+    if !chart_file_path.exists() {
+        return Ok(None);
+    }
+    let index_path = match find_index_in_cache(helm_repos, repository_cache_path, repo_url) {
+        Some(index_path) => index_path,
+        None => {
+            debug!("Ignoring `{}`, since Helm's repository cache has no index.yaml for repo `{}` to tell which repo it is from.", chart_file_path.display(), repo_url);
+            return Ok(None);
+        }
+    };
+
+    let index_file = File::open(&index_path)
+        .with_context(|| format!("Could not open file `{}`", index_path.display()))?;
+    let index_yaml: Value = serde_yaml::from_reader(BufReader::new(index_file))
+        .with_context(|| format!("Could not parse Helm's cached index.yaml `{}`!", index_path.display()))?;
+    let chart_info = index_yaml.get("entries").and_then(|entries| entries.get(chart_name)).and_then(Value::as_sequence)
+        .and_then(|chart_infos| chart_infos.iter().find(|chart_info| chart_info.get("version").and_then(Value::as_str) == Some(chart_version)));
+    let chart_info = match chart_info {
+        Some(chart_info) => chart_info,
+        None => {
+            debug!("Ignoring `{}`, since the index.yaml of repo `{}` doesn't list version `{}` of chart `{}`.", chart_file_path.display(), repo_url, chart_version, chart_name);
+            return Ok(None);
+        }
+    };
+
+    // A provenance file names the digest of its archive, which the provenance verification checks
+    if extension == "tgz" {
+        let listed_digest = chart_info.get("digest").and_then(Value::as_str).unwrap_or_default();
+        if sha256_digest(&chart_file_path)? != listed_digest.to_lowercase() {
+            debug!("Ignoring `{}`, since its digest isn't the one the index.yaml of repo `{}` lists, it is from another repo.", chart_file_path.display(), repo_url);
+            return Ok(None);
+        }
+    }
+
+    Ok(Some(chart_file_path))
+}
+
+/// The keyring `helm verify` uses by default.
+pub fn default_keyring_path() -> Option<PathBuf> {
+    dirs::home_dir().map(|home_dir| home_dir.join(".gnupg").join("pubring.gpg"))
+}
+
+pub fn same_repo_url(first_url: &str, second_url: &str) -> bool {
+    first_url.trim_end_matches('/') == second_url.trim_end_matches('/')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    const CHART_ARCHIVE: &str = "not really a chart archive";
+
+    // A Helm cache with the index of repo `stable`, which lists `web` 1.0.0 with the digest of `CHART_ARCHIVE`, and
+    // `web` 2.0.0 with another one
+    fn write_helm_cache(dir: &TempDir) -> (Vec<HelmRepoEntry>, PathBuf) {
+        let repository_config_path = dir.path().join("repositories.yaml");
+        fs::write(&repository_config_path, "apiVersion: \"\"\nrepositories:\n  - name: stable\n    url: https://charts.example.com/stable/\n  - name: other\n    url: https://other.example.com\n").unwrap();
+        let repository_cache_path = dir.path().join("repository");
+        fs::create_dir(&repository_cache_path).unwrap();
+
+        fs::write(repository_cache_path.join("web-1.0.0.tgz"), CHART_ARCHIVE).unwrap();
+        let digest = sha256_digest(&repository_cache_path.join("web-1.0.0.tgz")).unwrap();
+        fs::write(repository_cache_path.join("web-2.0.0.tgz"), CHART_ARCHIVE).unwrap();
+        fs::write(repository_cache_path.join("web-2.0.0.tgz.prov"), "signed").unwrap();
+        fs::write(repository_cache_path.join("stable-index.yaml"), format!(
+            "entries:\n  web:\n    - version: 2.0.0\n      digest: {}\n    - version: 1.0.0\n      digest: {}\n", "0".repeat(64), digest.to_uppercase())).unwrap();
+
+        (read_repository_config_file(&repository_config_path).unwrap(), repository_cache_path)
+    }
+
+    #[test]
+    fn reads_the_repos_of_the_repositories_file() {
+        let dir = TempDir::new().unwrap();
+        let repository_config_path = dir.path().join("repositories.yaml");
+        fs::write(&repository_config_path, "apiVersion: \"\"\nrepositories:\n  - name: stable\n    url: https://charts.example.com/stable/\n    username: user\n    password: secret\n  - name: other\n    url: https://other.example.com\n").unwrap();
+
+        let helm_repos = read_repository_config_file(&repository_config_path).unwrap();
+        assert_eq!(helm_repos.iter().map(|helm_repo| (helm_repo.name.as_str(), helm_repo.url.as_str())).collect::<Vec<_>>(),
+                   vec![("stable", "https://charts.example.com/stable/"), ("other", "https://other.example.com")]);
+        assert_eq!(helm_repos[0].username.as_deref(), Some("user"));
+        assert!(helm_repos[1].password.is_none());
+
+        // A repositories file without any repo yet
+        fs::write(&repository_config_path, "apiVersion: \"\"\nrepositories:\n").unwrap();
+        assert!(read_repository_config_file(&repository_config_path).unwrap().is_empty());
+    }
+    #[test]
+    fn finds_the_cached_index_of_a_repo_by_its_url() {
+        let dir = TempDir::new().unwrap();
+        let (helm_repos, repository_cache_path) = write_helm_cache(&dir);
+
+        let url = |url: &str| Url::parse(url).unwrap();
+        assert_eq!(find_index_in_cache(&helm_repos, &repository_cache_path, &url("https://charts.example.com/stable")), Some(repository_cache_path.join("stable-index.yaml")));
+        // Not cached, or not a known repo
+        assert_eq!(find_index_in_cache(&helm_repos, &repository_cache_path, &url("https://other.example.com/")), None);
+        assert_eq!(find_index_in_cache(&helm_repos, &repository_cache_path, &url("https://unknown.example.com/")), None);
+    }
+
+    #[test]
+    fn finds_cached_chart_archives_with_the_listed_digest() {
+        let dir = TempDir::new().unwrap();
+        let (helm_repos, repository_cache_path) = write_helm_cache(&dir);
+        let find = |repo_url: &str, chart_version: &str, extension: &str| {
+            find_chart_file_in_cache(&helm_repos, &repository_cache_path, &Url::parse(repo_url).unwrap(), "web", chart_version, extension).unwrap()
+        };
+
+        assert_eq!(find("https://charts.example.com/stable/", "1.0.0", "tgz"), Some(repository_cache_path.join("web-1.0.0.tgz")));
+        // Another archive with the same name, from another repo
+        assert_eq!(find("https://charts.example.com/stable/", "2.0.0", "tgz"), None);
+        // Provenance files aren't checked against the archive's digest
+        assert_eq!(find("https://charts.example.com/stable/", "2.0.0", "tgz.prov"), Some(repository_cache_path.join("web-2.0.0.tgz.prov")));
+        // Not in the cache, or from a repo without a cached index
+        assert_eq!(find("https://charts.example.com/stable/", "3.0.0", "tgz"), None);
+        assert_eq!(find("https://other.example.com/", "1.0.0", "tgz"), None);
+    }
+}
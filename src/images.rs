@@ -0,0 +1,259 @@
+use std::collections::BTreeMap;
+use serde_yaml::Value;
+use log::{debug, warn};
+
+use crate::subcharts::ChartValues;
+use crate::values::{self, KeyPath};
+
+// Finds the container images a chart deploys, to tell which ones a chart update changes. Charts don't declare them in
+// a standard way, so they are taken from the usual shapes in the default values: maps with a `repository` (and
+// `registry`, `tag` or `digest`) next to it, and `repo:tag` strings under keys named like `image`. Charts that list
+// their images in the `artifacthub.io/images` annotation of their Chart.yaml add those.
+
+const IMAGES_ANNOTATION: &str = "artifacthub.io/images";
+
+/// A container image reference found in a chart.
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub repository: String,
+    // The tag, `@digest` or both, empty when the chart doesn't say
+    pub version: String,
+    // The key path it was found at, or the annotation
+    pub source: String,
+}
+
+/// How the versions an image is used with changed between two chart versions.
+#[derive(Debug)]
+pub enum VersionChange {
+    Added(Vec<String>),
+    Removed(Vec<String>),
+    // The versions before and after, the same image can be used with several tags
+    Retagged(Vec<String>, Vec<String>),
+}
+
+/// Returns the images in the default values and the Chart.yaml of a chart. Empty tags default to the appVersion of
+/// the (sub)chart the key belongs to, like most charts do in their templates.
+pub fn find_images(defaults: &Value, chart_values: &ChartValues, chart_file: &Value) -> Vec<Image> {
+    let mut images = Vec::new();
+    collect_images(defaults, &mut Vec::new(), chart_values, &mut images);
+
+    let annotated_images = chart_file.get("annotations")
+        .and_then(|annotations| annotations.get(IMAGES_ANNOTATION))
+        .and_then(Value::as_str);
+    if let Some(annotated_images) = annotated_images {
+        match serde_yaml::from_str::<Vec<Value>>(annotated_images) {
+            Ok(annotated_images) => images.extend(annotated_images.iter()
+                .filter_map(|annotated_image| annotated_image.get("image").and_then(Value::as_str))
+                .filter_map(|reference| parse_reference(reference, String::from(IMAGES_ANNOTATION)))),
+            Err(e) => warn!("Couldn't parse the `{}` annotation of the Chart.yaml, so its images are ignored! {}", IMAGES_ANNOTATION, e),
+        }
+    }
+
+    debug!("Found {} image reference(s).", images.len());
+    images
+}
+
+fn collect_images(value: &Value, key_path: &mut KeyPath, chart_values: &ChartValues, images: &mut Vec<Image>) {
+    match value {
+        Value::Mapping(mapping) => {
+            let get = |key: &str| mapping.get(&Value::String(String::from(key))).and_then(scalar_to_string).filter(|value| !value.is_empty());
+            if let Some(repository) = get("repository") {
+                let repository = match get("registry") {
+                    Some(registry) => format!("{}/{}", registry.trim_end_matches('/'), repository),
+                    None => repository,
+                };
+                let version = match (get("tag"), get("digest")) {
+                    (Some(tag), Some(digest)) => format!("{}@{}", tag, digest),
+                    (Some(tag), None) => tag,
+                    (None, Some(digest)) => format!("@{}", digest),
+                    (None, None) => String::from(chart_values.app_version(key_path).unwrap_or_default()),
+                };
+                images.push(Image { repository: normalize_repository(&repository), version, source: values::format_key_path(key_path) });
+                return;
+            }
+
+            for (key, value) in mapping {
+                let key = scalar_to_string(key).unwrap_or_default();
+                key_path.push(key.clone());
+                match value {
+                    Value::String(reference) if key.to_lowercase().contains("image") => {
+                        images.extend(parse_reference(reference, values::format_key_path(key_path)));
+                    }
+                    value => collect_images(value, key_path, chart_values, images),
+                }
+                key_path.pop();
+            }
+        }
+        Value::Sequence(sequence) => {
+            for (index, value) in sequence.iter().enumerate() {
+                key_path.push(index.to_string());
+                collect_images(value, key_path, chart_values, images);
+                key_path.pop();
+            }
+        }
+        _ => {}
+    }
+}
+
+// Only full references count, a lone name under a key like `image` is too likely something else
+fn parse_reference(reference: &str, source: String) -> Option<Image> {
+    let reference = reference.trim();
+    if reference.is_empty() || reference.contains(char::is_whitespace) {
+        return None;
+    }
+
+    let (name, digest) = match reference.split_once('@') {
+        Some((name, digest)) => (name, Some(digest)),
+        None => (reference, None),
+    };
+    // A colon before the last slash belongs to the registry's port
+    let (repository, tag) = match name.rfind(':').filter(|colon| name.rfind('/').is_none_or(|slash| *colon > slash)) {
+        Some(colon) => (&name[..colon], Some(&name[colon + 1..])),
+        None => (name, None),
+    };
+    let version = match (tag, digest) {
+        (Some(tag), Some(digest)) => format!("{}@{}", tag, digest),
+        (Some(tag), None) => String::from(tag),
+        (None, Some(digest)) => format!("@{}", digest),
+        (None, None) => return None,
+    };
+
+    Some(Image { repository: normalize_repository(repository), version, source })
+}
+
+// `nginx`, `library/nginx` and `docker.io/library/nginx` are all the same image
+fn normalize_repository(repository: &str) -> String {
+    let repository = repository.strip_prefix("docker.io/").or_else(|| repository.strip_prefix("index.docker.io/")).unwrap_or(repository);
+    String::from(repository.strip_prefix("library/").unwrap_or(repository))
+}
+
+fn scalar_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(value) => Some(value.clone()),
+        Value::Number(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+/// Compares the images of two chart versions by repository. Returns every image that was added, removed or used with
+/// other versions, with where it is found in the chart.
+pub fn diff_images(original_images: &[Image], latest_images: &[Image]) -> Vec<(String, VersionChange, Vec<String>)> {
+    let group = |images: &[Image]| {
+        let mut grouped_images: BTreeMap<String, (Vec<String>, Vec<String>)> = BTreeMap::new();
+        for image in images {
+            let (versions, sources) = grouped_images.entry(image.repository.clone()).or_default();
+            if !versions.contains(&image.version) {
+                versions.push(image.version.clone());
+            }
+            if !sources.contains(&image.source) {
+                sources.push(image.source.clone());
+            }
+        }
+        for (versions, _) in grouped_images.values_mut() {
+            versions.sort();
+        }
+        grouped_images
+    };
+    let original_images = group(original_images);
+    let latest_images = group(latest_images);
+
+    let mut image_changes = Vec::new();
+    for (repository, (latest_versions, latest_sources)) in &latest_images {
+        match original_images.get(repository) {
+            None => image_changes.push((repository.clone(), VersionChange::Added(latest_versions.clone()), latest_sources.clone())),
+            Some((original_versions, _)) if original_versions != latest_versions => {
+                image_changes.push((repository.clone(), VersionChange::Retagged(original_versions.clone(), latest_versions.clone()), latest_sources.clone()));
+            }
+            Some(_) => {}
+        }
+    }
+    for (repository, (original_versions, original_sources)) in &original_images {
+        if !latest_images.contains_key(repository) {
+            image_changes.push((repository.clone(), VersionChange::Removed(original_versions.clone()), original_sources.clone()));
+        }
+    }
+
+    image_changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    use crate::subcharts;
+
+    fn parse(reference: &str) -> Option<(String, String)> {
+        parse_reference(reference, String::from("image")).map(|image| (image.repository, image.version))
+    }
+
+    fn image(repository: &str, version: &str, source: &str) -> Image {
+        Image { repository: String::from(repository), version: String::from(version), source: String::from(source) }
+    }
+
+    #[test]
+    fn parses_references_with_ports_and_digests() {
+        let pair = |repository: &str, version: &str| Some((String::from(repository), String::from(version)));
+        assert_eq!(parse("registry:5000/img:tag"), pair("registry:5000/img", "tag"));
+        assert_eq!(parse("img@sha256:abc"), pair("img", "@sha256:abc"));
+        assert_eq!(parse("img:tag@sha256:abc"), pair("img", "tag@sha256:abc"));
+        assert_eq!(parse(" quay.io/org/img:1.0 "), pair("quay.io/org/img", "1.0"));
+        // Without a tag or digest, or with spaces, it's not a reference
+        assert_eq!(parse("img"), None);
+        assert_eq!(parse("registry:5000/img"), None);
+        assert_eq!(parse("an image: nginx"), None);
+    }
+
+    #[test]
+    fn normalizes_docker_hub_repositories() {
+        assert_eq!(normalize_repository("docker.io/library/nginx"), "nginx");
+        assert_eq!(normalize_repository("index.docker.io/library/nginx"), "nginx");
+        assert_eq!(normalize_repository("library/nginx"), "nginx");
+        assert_eq!(normalize_repository("docker.io/bitnami/redis"), "bitnami/redis");
+        assert_eq!(normalize_repository("quay.io/library/nginx"), "quay.io/library/nginx");
+    }
+
+    #[test]
+    fn finds_images_in_maps_strings_and_the_annotation() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("Chart.yaml"), "name: app\nappVersion: \"2.1\"\n").unwrap();
+        let chart_values = subcharts::load_chart_values(dir.path()).unwrap();
+        let defaults = values::parse_values(concat!(
+            "image:\n  registry: docker.io\n  repository: library/nginx\n  tag: \"1.21\"\n",
+            "app:\n  image:\n    repository: org/app\n",
+            "sidecars:\n  - image: busybox:1.36\n  - name: no-image\n",
+            "proxyImage: envoyproxy/envoy@sha256:abc\n",
+            "imagePullPolicy: Always\n",
+        )).unwrap();
+        let chart_file = values::parse_values("annotations:\n  artifacthub.io/images: |\n    - name: job\n      image: org/job:3.0\n").unwrap();
+
+        let images: Vec<(String, String, String)> = find_images(&defaults, &chart_values, &chart_file).into_iter()
+            .map(|image| (image.repository, image.version, image.source))
+            .collect();
+        let triple = |repository: &str, version: &str, source: &str| (String::from(repository), String::from(version), String::from(source));
+        assert_eq!(images, vec![
+            triple("nginx", "1.21", "image"),
+            // Without a tag it's the appVersion
+            triple("org/app", "2.1", "app.image"),
+            triple("busybox", "1.36", "sidecars.0.image"),
+            triple("envoyproxy/envoy", "@sha256:abc", "proxyImage"),
+            triple("org/job", "3.0", IMAGES_ANNOTATION),
+        ]);
+    }
+
+    #[test]
+    fn diffs_images_by_repository() {
+        let original_images = vec![image("nginx", "1.19", "image"), image("busybox", "1.35", "init.image"), image("org/old", "1.0", "old.image")];
+        let latest_images = vec![image("nginx", "1.21", "image"), image("busybox", "1.35", "init.image"), image("org/new", "2.0", "new.image")];
+
+        let image_changes = diff_images(&original_images, &latest_images);
+        assert_eq!(image_changes.len(), 3);
+        assert!(matches!(&image_changes[0], (repository, VersionChange::Retagged(before, after), sources)
+            if repository == "nginx" && before == &["1.19"] && after == &["1.21"] && sources == &["image"]));
+        assert!(matches!(&image_changes[1], (repository, VersionChange::Added(versions), sources)
+            if repository == "org/new" && versions == &["2.0"] && sources == &["new.image"]));
+        assert!(matches!(&image_changes[2], (repository, VersionChange::Removed(versions), sources)
+            if repository == "org/old" && versions == &["1.0"] && sources == &["old.image"]));
+    }
+}
@@ -0,0 +1,195 @@
+use serde_yaml::Value;
+use log::debug;
+
+use crate::values::{self, KeyPath, ValueChange};
+use crate::yaml_text::{self, KeyBlock};
+
+// The line based merge brings the keys a new chart version adds with their comments and at the place upstream put
+// them, except when they end up on the chart's side of a conflict that is resolved by keeping our side. Those keys are
+// added to the merged values file afterwards: copied from the chart's values.yaml with the comments above them, next to
+// the same sibling keys as upstream (or in order, when our file sorts the keys and upstream doesn't) and indented like
+// the rest of our file.
+
+/// Adds the keys that are new in `latest_text` compared to `original_text`, and missing from `merged_text`, to
+/// `merged_text`. Returns the resulting text and the key paths that were added.
+pub fn add_new_keys(merged_text: &str, original_text: &str, latest_text: &str) -> (String, Vec<KeyPath>) {
+    // Helm only reads the first document, the others are left as they are
+    let (first_document, other_documents) = yaml_text::split_first_document(merged_text);
+    let (merged_values, original_values, latest_values) = match (values::parse_values(first_document), values::parse_values(original_text), values::parse_values(latest_text)) {
+        (Ok(merged_values), Ok(original_values), Ok(latest_values)) => (merged_values, original_values, latest_values),
+        _ => {
+            debug!("Couldn't parse the merged values or the chart's values, so no new keys are added.");
+            return (String::from(merged_text), Vec::new());
+        }
+    };
+
+    let latest_lines: Vec<&str> = yaml_text::split_first_document(latest_text).0.lines().collect();
+    let latest_blocks = yaml_text::find_key_blocks(&latest_lines);
+
+    let mut text = String::from(first_document);
+    let mut values = merged_values;
+    let mut added_key_paths = Vec::new();
+    for (key_path, change) in values::diff_values(&original_values, &latest_values) {
+        if !matches!(change, ValueChange::Added(_)) || values::has_key_path(&values, &key_path) || !is_copied_map(&values, &original_values, &key_path[..key_path.len() - 1]) {
+            continue;
+        }
+
+        // Moves the block as it is when re-indenting it doesn't give the chart's value, e.g. for a list of multi-line strings
+        let added = [true, false].iter()
+            .filter_map(|&scale_indent| insert_key(&text, &key_path, &latest_lines, &latest_blocks, scale_indent))
+            .find_map(|candidate_text| check_added_key(&candidate_text, &values, &latest_values, &key_path).map(|candidate_values| (candidate_text, candidate_values)));
+        match added {
+            Some((added_text, added_values)) => {
+                debug!("Added new key `{}` with its comments.", values::format_key_path(&key_path));
+                text = added_text;
+                values = added_values;
+                added_key_paths.push(key_path);
+            }
+            None => debug!("Couldn't add new key `{}` to the values file.", values::format_key_path(&key_path)),
+        }
+    }
+
+    (text + other_documents, added_key_paths)
+}
+
+// Slimmed values files only hold some of the keys on purpose, so keys are only added to maps that hold at least half of
+// the keys the chart had in them, as copies of the chart's values.yaml do
+fn is_copied_map(values: &Value, original_values: &Value, key_path: &[String]) -> bool {
+    let (map, original_map) = match (values::get_key_path(values, key_path), values::get_key_path(original_values, key_path)) {
+        (Some(Value::Mapping(map)), Some(Value::Mapping(original_map))) => (map, original_map),
+        _ => return false,
+    };
+    let copied_key_count = original_map.iter().filter(|(key, _)| map.contains_key(key)).count();
+    copied_key_count * 2 >= original_map.len()
+}
+
+// Returns the values of `candidate_text` if it only adds the key, with the chart's value, to `values`
+fn check_added_key(candidate_text: &str, values: &Value, latest_values: &Value, key_path: &[String]) -> Option<Value> {
+    let candidate_values = values::parse_values(candidate_text).ok()?;
+    if values::get_key_path(&candidate_values, key_path) != values::get_key_path(latest_values, key_path) {
+        return None;
+    }
+
+    let mut remaining_values = candidate_values.clone();
+    let parent_value = key_path[..key_path.len() - 1].iter()
+        .try_fold(&mut remaining_values, |value, key| value.as_mapping_mut()?.get_mut(&Value::String(key.clone())))?;
+    parent_value.as_mapping_mut()?.remove(&Value::String(key_path[key_path.len() - 1].clone()));
+    (remaining_values == *values).then_some(candidate_values)
+}
+
+// Inserts the key's block from the chart's values.yaml into `text`, or returns `None` if there is no place for it
+fn insert_key(text: &str, key_path: &[String], latest_lines: &[&str], latest_blocks: &[KeyBlock], scale_indent: bool) -> Option<String> {
+    let lines: Vec<&str> = text.lines().collect();
+    let blocks = yaml_text::find_key_blocks(&lines);
+    let (key, parent_key_path) = key_path.split_last()?;
+    let is_sibling = |block: &&KeyBlock| block.key_path.len() == key_path.len() && block.key_path.starts_with(parent_key_path);
+    let siblings: Vec<&KeyBlock> = blocks.iter().filter(is_sibling).collect();
+    let latest_siblings: Vec<&KeyBlock> = latest_blocks.iter().filter(is_sibling).collect();
+    let latest_position = latest_siblings.iter().position(|block| block.key_path == key_path)?;
+    let latest_block = latest_siblings[latest_position];
+    let parent_block = blocks.iter().find(|block| block.key_path == parent_key_path);
+    if !parent_key_path.is_empty() && parent_block.is_none() {
+        return None;
+    }
+
+    let indent_unit = yaml_text::indent_unit(&blocks);
+    let indent = match (siblings.first(), parent_block) {
+        (Some(sibling), _) => sibling.indent,
+        (None, Some(parent_block)) => parent_block.indent + indent_unit,
+        (None, None) => 0,
+    };
+
+    let is_sorted = |blocks: &[&KeyBlock]| blocks.windows(2).all(|pair| pair[0].key_path <= pair[1].key_path);
+    let find_sibling = |latest_sibling: &&KeyBlock| siblings.iter().find(|sibling| sibling.key_path == latest_sibling.key_path).copied();
+    let insert_before = |sibling: &KeyBlock| if sibling.blank_line_before { sibling.start - 1 } else { sibling.start };
+    let line_index = if siblings.len() >= 3 && is_sorted(&siblings) && !is_sorted(&latest_siblings) {
+        match siblings.iter().find(|sibling| sibling.key_path.last() > Some(key)) {
+            Some(next_sibling) => insert_before(next_sibling),
+            None => siblings.last()?.end,
+        }
+    } else if let Some(previous_sibling) = latest_siblings[..latest_position].iter().rev().find_map(find_sibling) {
+        previous_sibling.end
+    } else if let Some(next_sibling) = latest_siblings[latest_position + 1..].iter().find_map(find_sibling) {
+        insert_before(next_sibling)
+    } else if let Some(parent_block) = parent_block {
+        parent_block.end
+    } else {
+        lines.iter().rposition(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#')).map_or(lines.len(), |line_index| line_index + 1)
+    };
+
+    let latest_indent_unit = yaml_text::indent_unit(latest_blocks);
+    let mut block_lines: Vec<String> = Vec::new();
+    // Our file decides whether keys are separated by blank lines
+    let uses_blank_lines = siblings.iter().any(|sibling| sibling.blank_line_before);
+    if latest_block.blank_line_before && uses_blank_lines && line_index > 0 && !lines[line_index - 1].trim().is_empty() {
+        block_lines.push(String::new());
+    }
+    // The content of a multi-line string moves along with its key line, re-indenting it would change the string
+    let mut multi_line_string: Option<(usize, usize)> = None;
+    for latest_line in &latest_lines[latest_block.start..latest_block.end] {
+        let trimmed_line = latest_line.trim_start();
+        if trimmed_line.is_empty() {
+            block_lines.push(String::new());
+            continue;
+        }
+        let latest_line_indent = latest_line.len() - trimmed_line.len();
+        let line_indent = match multi_line_string {
+            Some((key_line_indent, new_key_line_indent)) if latest_line_indent > key_line_indent => new_key_line_indent + (latest_line_indent - key_line_indent),
+            _ => {
+                let relative_indent = latest_line_indent.saturating_sub(latest_block.indent);
+                let relative_indent = if scale_indent {
+                    relative_indent / latest_indent_unit * indent_unit + relative_indent % latest_indent_unit
+                } else {
+                    relative_indent
+                };
+                let line_indent = indent + relative_indent;
+                multi_line_string = yaml_text::split_key_line(trimmed_line)
+                    .filter(|(_, value)| yaml_text::starts_block_scalar(value))
+                    .map(|_| (latest_line_indent, line_indent));
+                line_indent
+            }
+        };
+        block_lines.push(format!("{}{}", " ".repeat(line_indent), trimmed_line));
+    }
+
+    let mut inserted_text: String = lines[..line_index].iter().map(|line| format!("{}\n", line)).collect();
+    inserted_text.extend(block_lines.iter().map(|line| format!("{}\n", line)));
+    inserted_text.extend(lines[line_index..].iter().map(|line| format!("{}\n", line)));
+    Some(inserted_text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGINAL_TEXT: &str = "image:\n  repository: nginx\n  registry: docker.io\n  tag: \"1.19\"\nservice:\n  port: 80\n";
+    const LATEST_TEXT: &str = "image:\n  repository: nginx\n  registry: docker.io\n  # when to pull the image\n  pullPolicy: IfNotPresent\n  tag: \"1.21\"\nservice:\n  port: 80\n";
+
+    #[test]
+    fn adds_new_keys_with_their_comments_after_the_same_sibling() {
+        let merged_text = "image:\n    repository: nginx\n    registry: docker.io\n    tag: \"1.17\"\nservice:\n    port: 8080\n";
+        let (text, added_key_paths) = add_new_keys(merged_text, ORIGINAL_TEXT, LATEST_TEXT);
+        assert_eq!(text, "image:\n    repository: nginx\n    registry: docker.io\n    # when to pull the image\n    pullPolicy: IfNotPresent\n    tag: \"1.17\"\nservice:\n    port: 8080\n");
+        assert_eq!(added_key_paths, vec![vec![String::from("image"), String::from("pullPolicy")]]);
+    }
+
+    #[test]
+    fn adds_new_keys_in_order_to_sorted_maps() {
+        let merged_text = "image:\n  registry: docker.io\n  repository: nginx\n  tag: \"1.17\"\nservice:\n  port: 8080\n";
+        let (text, _) = add_new_keys(merged_text, ORIGINAL_TEXT, LATEST_TEXT);
+        assert_eq!(text, "image:\n  # when to pull the image\n  pullPolicy: IfNotPresent\n  registry: docker.io\n  repository: nginx\n  tag: \"1.17\"\nservice:\n  port: 8080\n");
+    }
+
+    #[test]
+    fn leaves_slimmed_maps_alone() {
+        let merged_text = "image:\n  tag: \"1.17\"\n";
+        assert_eq!(add_new_keys(merged_text, ORIGINAL_TEXT, LATEST_TEXT), (String::from(merged_text), Vec::new()));
+    }
+
+    #[test]
+    fn doesnt_add_keys_that_are_there_already() {
+        let (text, added_key_paths) = add_new_keys(LATEST_TEXT, ORIGINAL_TEXT, LATEST_TEXT);
+        assert_eq!(text, LATEST_TEXT);
+        assert!(added_key_paths.is_empty());
+    }
+}
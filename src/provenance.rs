@@ -0,0 +1,126 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use serde_yaml::Value;
+use tempfile::TempDir;
+use anyhow::{Context, Result};
+use log::{debug, trace};
+
+use crate::fetch::{generate_rand_filename, sha256_digest};
+
+// Verification of Helm provenance files (https://helm.sh/docs/topics/provenance/), the same checks
+// `helm verify` does: the .prov file has to be signed by a key in the keyring and it has to list the
+// sha256 digest of the chart archive. Like the values merge, this relies on an external tool, `gpg`.
+
+/// Verifies the chart archive at `archive_path` with its provenance file and returns who signed it.
+/// `archive_file_name` is the name the archive has in its repo, which is what the provenance file refers to.
+pub fn verify_provenance(tmp_dir: &TempDir, archive_path: &Path, archive_file_name: &str, provenance_path: &Path, keyring_path: &Path) -> Result<String> {
+    debug!("Attempting to verify chart archive `{}` with provenance file `{}` and keyring `{}`.", archive_file_name, provenance_path.display(), keyring_path.display());
+
+    if !keyring_path.exists() {
+        return Err(anyhow::anyhow!("Keyring `{}` doesn't exist!", keyring_path.display()));
+    }
+    let keyring_path = keyring_path.canonicalize()
+        .with_context(|| format!("Couldn't resolve the path of keyring `{}`!", keyring_path.display()))?;
+
+    // gpg needs a home dir, use a throwaway one to not touch the user's
+    let gpg_home_path = tmp_dir.path().join(generate_rand_filename());
+    fs::create_dir(&gpg_home_path)
+        .with_context(|| format!("Couldn't create gpg home dir `{}`!", gpg_home_path.display()))?;
+    let signed_content_path = tmp_dir.path().join(generate_rand_filename());
+
+    let output = Command::new("gpg")
+        .arg("--batch")
+        .arg("--no-permission-warning")
+        .arg("--homedir").arg(&gpg_home_path)
+        .arg("--no-default-keyring")
+        .arg("--keyring").arg(&keyring_path)
+        .arg("--status-fd").arg("1")
+        .arg("--output").arg(&signed_content_path)
+        .arg("--decrypt").arg(provenance_path)
+        .output()
+        .with_context(|| "Couldn't run `gpg` to verify the provenance file! Is it installed?")?;
+
+    let status = String::from_utf8_lossy(&output.stdout);
+    trace!("gpg status output: `{}`", status);
+
+    let signer = match find_signer(&status) {
+        Some(signer) if output.status.success() => signer,
+        _ => return Err(anyhow::anyhow!("The signature of provenance file for `{}` couldn't be verified with keyring `{}`: {}",
+                                        archive_file_name, keyring_path.display(), String::from_utf8_lossy(&output.stderr).trim())),
+    };
+
+    let signed_content = fs::read_to_string(&signed_content_path)
+        .with_context(|| format!("Couldn't read the signed content of provenance file `{}`!", provenance_path.display()))?;
+    let expected_digest = get_archive_digest(&signed_content, archive_file_name)?;
+    let archive_digest = format!("sha256:{}", sha256_digest(archive_path)?);
+
+    if expected_digest != archive_digest {
+        return Err(anyhow::anyhow!("The digest `{}` of chart archive `{}` doesn't match the digest `{}` signed in its provenance file!",
+                                   archive_digest, archive_file_name, expected_digest));
+    }
+
+    debug!("Chart archive `{}` was verified successfully, signed by `{}`.", archive_file_name, signer);
+    Ok(signer)
+}
+
+// The user id of a good and valid signature in gpg's `--status-fd` output
+fn find_signer(status: &str) -> Option<String> {
+    let signer = status.lines()
+        .find_map(|line| line.strip_prefix("[GNUPG:] GOODSIG "))
+        .map(|good_signature| good_signature.split_once(' ').map_or(good_signature, |(_, user_id)| user_id).to_string())?;
+    Some(signer).filter(|_| status.lines().any(|line| line.starts_with("[GNUPG:] VALIDSIG ")))
+}
+
+// The signed content is the Chart.yaml, followed by a `...` line and a YAML document with the digests of the files
+fn get_archive_digest(signed_content: &str, archive_file_name: &str) -> Result<String> {
+    let (_, files_document) = signed_content.split_once("\n...\n")
+        .with_context(|| "The provenance file doesn't contain the digests of the chart files!")?;
+    let files_value: Value = serde_yaml::from_str(files_document)
+        .with_context(|| "Couldn't parse the digests of the chart files in the provenance file!")?;
+    let files = files_value.get("files").and_then(Value::as_mapping)
+        .with_context(|| "The provenance file doesn't have a `files` section!")?;
+
+    let digest = files.get(&Value::from(archive_file_name))
+        .with_context(|| format!("The provenance file doesn't list the digest of `{}`!", archive_file_name))?
+        .as_str()
+        .with_context(|| format!("The digest of `{}` in the provenance file is not a proper String!", archive_file_name))?;
+
+    Ok(String::from(digest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIGNED_CONTENT: &str = "apiVersion: v2\nname: mychart\nversion: 2.0.0\n\n...\nfiles:\n  mychart-2.0.0.tgz: sha256:abc123\n";
+
+    #[test]
+    fn reads_the_archive_digest() {
+        assert_eq!(get_archive_digest(SIGNED_CONTENT, "mychart-2.0.0.tgz").unwrap(), "sha256:abc123");
+    }
+
+    #[test]
+    fn fails_for_an_archive_that_isnt_listed() {
+        assert!(get_archive_digest(SIGNED_CONTENT, "mychart-1.0.0.tgz").is_err());
+    }
+
+    #[test]
+    fn fails_without_the_files_document() {
+        assert!(get_archive_digest("apiVersion: v2\nname: mychart\n", "mychart-2.0.0.tgz").is_err());
+        assert!(get_archive_digest("name: mychart\n...\nother: {}\n", "mychart-2.0.0.tgz").is_err());
+    }
+
+    #[test]
+    fn finds_the_signer_of_a_valid_signature() {
+        let status = "[GNUPG:] NEWSIG\n[GNUPG:] GOODSIG 1234ABCD Helm Signer <signer@example.com>\n[GNUPG:] VALIDSIG 1234ABCD 2020-01-01\n";
+        assert_eq!(find_signer(status).as_deref(), Some("Helm Signer <signer@example.com>"));
+    }
+
+    #[test]
+    fn rejects_good_signatures_that_arent_valid() {
+        // A GOODSIG alone, without a VALIDSIG, isn't enough
+        assert_eq!(find_signer("[GNUPG:] GOODSIG 1234ABCD Helm Signer\n"), None);
+        assert_eq!(find_signer("[GNUPG:] BADSIG 1234ABCD Helm Signer\n"), None);
+    }
+}
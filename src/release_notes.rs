@@ -0,0 +1,144 @@
+use serde_yaml::Value;
+use log::{debug, warn};
+
+use crate::report::{LinkReport, ReleaseNoteReport, ReleaseNotesReport};
+
+// Charts published on Artifact Hub describe the changes of every version in the `artifacthub.io/changes` annotation
+// of their Chart.yaml, which `helm repo index` copies to the index.yaml, and link to their docs and sources in the
+// `artifacthub.io/links` one. Both are YAML lists inside a string.
+
+const CHANGES_ANNOTATION: &str = "artifacthub.io/changes";
+const LINKS_ANNOTATION: &str = "artifacthub.io/links";
+
+/// Collects the changes and links annotated on the chart versions in `chart_infos`, which are index.yaml entries
+/// or Chart.yaml files, newest first.
+pub fn collect_release_notes(chart_infos: &[&Value]) -> ReleaseNotesReport {
+    let mut release_notes = ReleaseNotesReport::default();
+    for chart_info in chart_infos {
+        let version = chart_info.get("version").and_then(Value::as_str).unwrap_or_default();
+        let annotations = match chart_info.get("annotations") {
+            Some(annotations) => annotations,
+            None => continue,
+        };
+
+        for change in parse_annotation(annotations, CHANGES_ANNOTATION, version) {
+            // Older charts list the changes as plain strings, without a kind
+            let (kind, description, links) = match &change {
+                Value::String(description) => (String::from("changed"), description.clone(), Vec::new()),
+                change => (
+                    change.get("kind").and_then(Value::as_str).unwrap_or("changed").to_lowercase(),
+                    String::from(change.get("description").and_then(Value::as_str).unwrap_or_default()),
+                    change.get("links").and_then(Value::as_sequence).map(|links| parse_links(links)).unwrap_or_default(),
+                ),
+            };
+            let release_note = ReleaseNoteReport { version: String::from(version), description, links };
+            match kind.as_str() {
+                "added" => release_notes.added.push(release_note),
+                "deprecated" => release_notes.deprecated.push(release_note),
+                "removed" => release_notes.removed.push(release_note),
+                "fixed" => release_notes.fixed.push(release_note),
+                "security" => release_notes.security.push(release_note),
+                _ => release_notes.changed.push(release_note),
+            }
+        }
+
+        for link in parse_links(&parse_annotation(annotations, LINKS_ANNOTATION, version)) {
+            if !release_notes.links.iter().any(|known_link| known_link.url == link.url) {
+                release_notes.links.push(link);
+            }
+        }
+    }
+
+    debug!("Found {} release note(s) and {} link(s).", release_notes.change_count(), release_notes.links.len());
+    release_notes
+}
+
+fn parse_annotation(annotations: &Value, annotation: &str, version: &str) -> Vec<Value> {
+    let annotation_text = match annotations.get(annotation).and_then(Value::as_str) {
+        Some(annotation_text) => annotation_text,
+        None => return Vec::new(),
+    };
+    serde_yaml::from_str(annotation_text).unwrap_or_else(|e| {
+        warn!("Couldn't parse the `{}` annotation of version `{}`, so it is ignored! {}", annotation, version, e);
+        Vec::new()
+    })
+}
+
+fn parse_links(links: &[Value]) -> Vec<LinkReport> {
+    links.iter()
+        .filter_map(|link| {
+            let url = link.get("url").and_then(Value::as_str)?;
+            let name = link.get("name").and_then(Value::as_str).unwrap_or(url);
+            Some(LinkReport { name: String::from(name), url: String::from(url) })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chart_info(chart_info_text: &str) -> Value {
+        serde_yaml::from_str(chart_info_text).unwrap()
+    }
+
+    fn notes(release_notes: &[ReleaseNoteReport]) -> Vec<(&str, &str)> {
+        release_notes.iter().map(|release_note| (release_note.version.as_str(), release_note.description.as_str())).collect()
+    }
+
+    #[test]
+    fn groups_the_changes_of_all_versions_by_kind() {
+        let latest = chart_info(concat!(
+            "version: 2.0.0\n",
+            "annotations:\n",
+            "  artifacthub.io/changes: |\n",
+            "    - kind: added\n",
+            "      description: Support for sidecars\n",
+            "      links:\n",
+            "        - name: PR\n",
+            "          url: https://example.com/pull/2\n",
+            "    - kind: Security\n",
+            "      description: Bump the image for a CVE\n",
+            "    - kind: unknown\n",
+            "      description: Something else\n",
+            "  artifacthub.io/links: |\n",
+            "    - name: Source\n",
+            "      url: https://example.com/chart\n",
+        ));
+        let previous = chart_info(concat!(
+            "version: 1.1.0\n",
+            "annotations:\n",
+            "  artifacthub.io/changes: |\n",
+            "    - Plain string change\n",
+            "    - kind: added\n",
+            "      description: An ingress\n",
+            "  artifacthub.io/links: |\n",
+            "    - url: https://example.com/chart\n",
+            "    - url: https://example.com/docs\n",
+        ));
+
+        let release_notes = collect_release_notes(&[&latest, &previous]);
+        assert_eq!(notes(&release_notes.added), vec![("2.0.0", "Support for sidecars"), ("1.1.0", "An ingress")]);
+        assert_eq!(notes(&release_notes.security), vec![("2.0.0", "Bump the image for a CVE")]);
+        // Plain strings and unknown kinds are changes
+        assert_eq!(notes(&release_notes.changed), vec![("2.0.0", "Something else"), ("1.1.0", "Plain string change")]);
+        assert!(release_notes.fixed.is_empty() && release_notes.deprecated.is_empty() && release_notes.removed.is_empty());
+        assert_eq!(release_notes.added[0].links.iter().map(|link| (link.name.as_str(), link.url.as_str())).collect::<Vec<_>>(),
+                   vec![("PR", "https://example.com/pull/2")]);
+        // The same link is listed once, links without a name are named after their url
+        assert_eq!(release_notes.links.iter().map(|link| (link.name.as_str(), link.url.as_str())).collect::<Vec<_>>(),
+                   vec![("Source", "https://example.com/chart"), ("https://example.com/docs", "https://example.com/docs")]);
+    }
+
+    #[test]
+    fn skips_invalid_annotations() {
+        let invalid = chart_info("version: 1.2.0\nannotations:\n  artifacthub.io/changes: \"kind: [added\"\n  artifacthub.io/links: \"not a list\"\n");
+        let without_annotations = chart_info("version: 1.1.0\n");
+        let valid = chart_info("version: 1.0.0\nannotations:\n  artifacthub.io/changes: |\n    - kind: fixed\n      description: A crash\n");
+
+        let release_notes = collect_release_notes(&[&invalid, &without_annotations, &valid]);
+        assert_eq!(release_notes.change_count(), 1);
+        assert_eq!(notes(&release_notes.fixed), vec![("1.0.0", "A crash")]);
+        assert!(release_notes.links.is_empty());
+    }
+}
@@ -0,0 +1,495 @@
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::path::Path;
+use serde::Serialize;
+use anyhow::{Context, Result};
+use log::debug;
+
+use crate::chart_diff::FileChange;
+use crate::conflicts::Side;
+use crate::crds::CrdChange;
+
+// Summary of a run, written as JSON with `--json-report` for machines and as Markdown with `--markdown-report`,
+// e.g. for the description of a pull request.
+
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    pub apps: Vec<AppReport>,
+    // Why the run failed, the apps it didn't get to aren't listed
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppReport {
+    pub dsf: String,
+    pub name: String,
+    pub repo: String,
+    pub chart: String,
+    pub current_version: String,
+    pub latest_version: String,
+    pub archives: Vec<ArchiveReport>,
+    pub provenance: Option<ProvenanceReport>,
+    pub values_files: Vec<ValuesFileReport>,
+    pub default_changes: Vec<DefaultChangeReport>,
+    pub image_changes: Vec<ImageChangeReport>,
+    // From the Artifact Hub annotations of the versions after the current one, up to the reached one
+    pub release_notes: ReleaseNotesReport,
+    // Only with `--chart-docs`
+    pub chart_docs: Option<ChartDocsReport>,
+    // Only with `--chart-diff`
+    pub chart_diff: Option<ChartDiffReport>,
+    pub crd_changes: Vec<CrdChangeReport>,
+    // CRD changes need manual steps, so they get a flag of their own
+    pub has_crd_changes: bool,
+    // Why a chart archive of the app didn't match its digest in the index.yaml, which kept the app from being updated
+    pub digest_error: Option<String>,
+    // The versions the values files were merged through one after the other, empty when they were merged directly
+    pub merge_steps: Vec<String>,
+    // The version the values files and the DSF were updated to, short of the latest one when a step had conflicts
+    pub reached_version: String,
+    pub dsf_updated: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchiveReport {
+    pub version: String,
+    pub url: String,
+    pub digest: String,
+    pub digest_verified: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProvenanceReport {
+    pub verified: bool,
+    pub signer: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValuesFileReport {
+    pub path: String,
+    pub overlay: bool,
+    pub merge: Option<MergeOutcome>,
+    pub orphaned_overrides: Vec<OrphanedOverrideReport>,
+    pub auto_resolutions: Vec<AutoResolutionReport>,
+    pub conflicts: Vec<ConflictReport>,
+    // Where the merge result with conflict markers went, when it didn't go into the values file
+    pub conflict_file: Option<String>,
+    // Why the merged values file doesn't parse, when it doesn't
+    pub invalid_yaml: Option<String>,
+    // Where the merged values don't match the latest chart's values.schema.json
+    pub schema_violations: Vec<SchemaViolationReport>,
+    // Keys the chart added that the merge left out, which were copied from the chart's values.yaml with their comments
+    pub added_keys: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConflictReport {
+    pub keys: Vec<String>,
+    pub ours: String,
+    pub theirs: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SchemaViolationReport {
+    pub key: String,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AutoResolutionReport {
+    pub keys: Vec<String>,
+    pub kept: Side,
+    pub policies: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrphanedOverrideReport {
+    pub key: String,
+    pub suggestion: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DefaultChangeReport {
+    pub key: String,
+    pub change: DefaultChange,
+    pub old_value: Option<serde_yaml::Value>,
+    pub new_value: Option<serde_yaml::Value>,
+    // Whether one of the app's values files sets the key, so the new default doesn't apply to the app
+    pub overridden: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DefaultChange {
+    Added,
+    Removed,
+    Changed,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageChangeReport {
+    pub image: String,
+    pub change: ImageChange,
+    // Tags and/or `@digest`s, an image can be used with several of them
+    pub old_versions: Vec<String>,
+    pub new_versions: Vec<String>,
+    // The keys of the default values or the Chart.yaml annotation the image was found in
+    pub sources: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ImageChange {
+    Added,
+    Removed,
+    Retagged,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReleaseNotesReport {
+    pub added: Vec<ReleaseNoteReport>,
+    pub changed: Vec<ReleaseNoteReport>,
+    pub fixed: Vec<ReleaseNoteReport>,
+    pub security: Vec<ReleaseNoteReport>,
+    pub deprecated: Vec<ReleaseNoteReport>,
+    pub removed: Vec<ReleaseNoteReport>,
+    pub links: Vec<LinkReport>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReleaseNoteReport {
+    pub version: String,
+    pub description: String,
+    pub links: Vec<LinkReport>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkReport {
+    pub name: String,
+    pub url: String,
+}
+
+impl ReleaseNotesReport {
+    /// Returns the release notes grouped by kind, in the order they should be read.
+    pub fn kinds(&self) -> [(&'static str, &[ReleaseNoteReport]); 6] {
+        [
+            ("Added", &self.added),
+            ("Changed", &self.changed),
+            ("Fixed", &self.fixed),
+            ("Security", &self.security),
+            ("Deprecated", &self.deprecated),
+            ("Removed", &self.removed),
+        ]
+    }
+
+    pub fn change_count(&self) -> usize {
+        self.kinds().iter().map(|(_, release_notes)| release_notes.len()).sum()
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChartDocsReport {
+    // The new entries of the CHANGELOG.md, when they can be told apart
+    pub changelog: Option<String>,
+    pub parameter_changes: Vec<ParameterChangeReport>,
+    pub notes_diff: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ParameterChangeReport {
+    pub parameter: String,
+    pub change: DefaultChange,
+    // The other cells of the README's parameters table row
+    pub old_row: Option<String>,
+    pub new_row: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChartDiffReport {
+    // Where the full diff of all files went
+    pub diff_file: String,
+    pub files: Vec<ChartFileReport>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChartFileReport {
+    pub path: String,
+    pub change: FileChange,
+    // Only for templates, cut after `--max-inline-diff-lines` lines
+    pub diff: Option<String>,
+    pub diff_truncated: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CrdChangeReport {
+    pub name: String,
+    pub change: CrdChange,
+    // The file of the newest version it is in
+    pub source: String,
+    // Helm never upgrades or deletes the CRDs in `crds/` dirs, only the ones in templates
+    pub upgraded_by_helm: bool,
+    pub old_versions: Vec<String>,
+    pub new_versions: Vec<String>,
+    pub schema_changes: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MergeOutcome {
+    Clean,
+    Conflicts,
+    // Merged without conflicts, but the result isn't valid YAML or doesn't match the chart's schema
+    Invalid,
+}
+
+impl Report {
+    pub fn write_json(&self, report_path: &Path) -> Result<()> {
+        debug!("Attempting to write the JSON report to `{}`.", report_path.display());
+
+        let report_file = File::create(report_path)
+            .with_context(|| format!("Couldn't create the JSON report file `{}`!", report_path.display()))?;
+        serde_json::to_writer_pretty(report_file, self)
+            .with_context(|| format!("Couldn't write the JSON report to `{}`!", report_path.display()))?;
+
+        debug!("JSON report was written successfully to `{}`.", report_path.display());
+        Ok(())
+    }
+
+    pub fn write_markdown(&self, report_path: &Path) -> Result<()> {
+        debug!("Attempting to write the Markdown report to `{}`.", report_path.display());
+
+        fs::write(report_path, self.to_markdown())
+            .with_context(|| format!("Couldn't write the Markdown report to `{}`!", report_path.display()))?;
+
+        debug!("Markdown report was written successfully to `{}`.", report_path.display());
+        Ok(())
+    }
+
+    fn to_markdown(&self) -> String {
+        let mut markdown = String::from("# Chart updates\n");
+        if let Some(error) = &self.error {
+            let _ = writeln!(markdown, "\n> **Error:** hmum failed, apps it didn't get to aren't listed: {}", error.replace('\n', " "));
+        }
+        if self.apps.iter().all(|app| app.current_version == app.latest_version) {
+            markdown.push_str("\nAll apps use the latest version of their chart.\n");
+        }
+        let crd_app_names: Vec<&str> = self.apps.iter().filter(|app| app.has_crd_changes).map(|app| app.name.as_str()).collect();
+        if !crd_app_names.is_empty() {
+            let _ = writeln!(markdown, "\n> **Warning:** the CRDs of `{}` change. Helm doesn't upgrade the CRDs in `crds/` dirs and changed schemas can break existing resources, so plan the manual steps before deploying.",
+                             crd_app_names.join("`, `"));
+        }
+
+        for app in self.apps.iter().filter(|app| app.current_version != app.latest_version) {
+            let _ = writeln!(markdown, "\n## `{}`: `{}/{}` `{}` → `{}`{}\n", app.name, app.repo, app.chart, app.current_version, app.latest_version,
+                             if app.has_crd_changes { " (**CRD changes**)" } else { "" });
+            let _ = writeln!(markdown, "- DSF: `{}`{}", app.dsf, if app.dsf_updated { "" } else { " (not updated)" });
+
+            if !app.merge_steps.is_empty() {
+                let _ = writeln!(markdown, "- Merge steps: `{}` → `{}`", app.current_version, app.merge_steps.join("` → `"));
+            }
+            if app.reached_version != app.latest_version {
+                let _ = writeln!(markdown, "- **Stopped at `{}`** because of conflicts, resolve them and run hmum again to continue", app.reached_version);
+            }
+            if let Some(digest_error) = &app.digest_error {
+                let _ = writeln!(markdown, "- **Not updated**, a chart archive failed the digest verification: {}", digest_error);
+            }
+            if let Some(provenance) = &app.provenance {
+                match (&provenance.signer, &provenance.error) {
+                    (Some(signer), _) if provenance.verified => { let _ = writeln!(markdown, "- Provenance: signed by `{}`", signer); }
+                    (_, error) => { let _ = writeln!(markdown, "- Provenance: **verification failed** {}", error.as_deref().unwrap_or_default()); }
+                }
+            }
+
+            for values_file in &app.values_files {
+                let outcome = match (&values_file.merge, values_file.overlay) {
+                    (Some(MergeOutcome::Clean), _) => "merged cleanly",
+                    (Some(MergeOutcome::Conflicts), _) => "**merged with conflicts**",
+                    (Some(MergeOutcome::Invalid), _) => "**merged, but the result is invalid**",
+                    (None, true) => "overlay, checked only",
+                    (None, false) => "not merged",
+                };
+                let _ = writeln!(markdown, "- Values file `{}`: {}", values_file.path, outcome);
+                if let Some(conflict_file) = &values_file.conflict_file {
+                    let _ = writeln!(markdown, "  - conflicts written to `{}`", conflict_file);
+                }
+                for conflict in &values_file.conflicts {
+                    let _ = writeln!(markdown, "  - conflict on `{}`", conflict.keys.join("`, `"));
+                }
+                if let Some(invalid_yaml) = &values_file.invalid_yaml {
+                    let _ = writeln!(markdown, "  - not valid YAML: {}", invalid_yaml);
+                }
+                for schema_violation in &values_file.schema_violations {
+                    let _ = writeln!(markdown, "  - `{}` doesn't match the chart's schema: {}", schema_violation.key, schema_violation.message);
+                }
+                for added_key in &values_file.added_keys {
+                    let _ = writeln!(markdown, "  - new key `{}` added from the chart's values.yaml", added_key);
+                }
+                for auto_resolution in &values_file.auto_resolutions {
+                    let _ = writeln!(markdown, "  - conflict on `{}` resolved by keeping {} (policy `{}`)", auto_resolution.keys.join("`, `"),
+                                     match auto_resolution.kept { Side::Ours => "ours", Side::Theirs => "the chart's" }, auto_resolution.policies.join("`, `"));
+                }
+                for orphaned_override in &values_file.orphaned_overrides {
+                    match &orphaned_override.suggestion {
+                        Some(suggestion) => { let _ = writeln!(markdown, "  - `{}` doesn't exist in the new chart, renamed to `{}`?", orphaned_override.key, suggestion); }
+                        None => { let _ = writeln!(markdown, "  - `{}` doesn't exist in the new chart", orphaned_override.key); }
+                    }
+                }
+            }
+
+            if !app.crd_changes.is_empty() {
+                markdown.push_str("\n### CRD changes\n\n> **Warning:** Helm doesn't upgrade or delete the CRDs in `crds/` dirs, apply these changes manually.\n\n| CRD | Change | Old versions | New versions | Found in | Schema changes |\n| --- | --- | --- | --- | --- | --- |\n");
+                for crd_change in &app.crd_changes {
+                    let _ = writeln!(markdown, "| `{}` | {} | {} | {} | `{}`{} | {} |",
+                                     crd_change.name,
+                                     match crd_change.change {
+                                         CrdChange::Added => "**added**",
+                                         CrdChange::Removed => "**removed**",
+                                         CrdChange::Changed => "**changed**",
+                                     },
+                                     markdown_versions(&crd_change.old_versions),
+                                     markdown_versions(&crd_change.new_versions),
+                                     crd_change.source,
+                                     if crd_change.upgraded_by_helm { "" } else { " (not upgraded by helm)" },
+                                     crd_change.schema_changes.join("<br>").replace('|', "\\|"));
+                }
+            }
+
+            if !app.default_changes.is_empty() {
+                markdown.push_str("\n### Changed defaults\n\n| Key | Change | Old default | New default | Overridden |\n| --- | --- | --- | --- | --- |\n");
+                for default_change in &app.default_changes {
+                    let _ = writeln!(markdown, "| `{}` | {} | {} | {} | {} |",
+                                     default_change.key,
+                                     match default_change.change {
+                                         DefaultChange::Added => "added",
+                                         DefaultChange::Removed => "removed",
+                                         DefaultChange::Changed => "changed",
+                                     },
+                                     markdown_value(&default_change.old_value),
+                                     markdown_value(&default_change.new_value),
+                                     if default_change.overridden { "yes" } else { "**no**" });
+                }
+            }
+
+            if !app.image_changes.is_empty() {
+                markdown.push_str("\n### Changed images\n\n| Image | Change | Old version | New version | Found in |\n| --- | --- | --- | --- | --- |\n");
+                for image_change in &app.image_changes {
+                    let _ = writeln!(markdown, "| `{}` | {} | {} | {} | `{}` |",
+                                     image_change.image,
+                                     match image_change.change {
+                                         ImageChange::Added => "added",
+                                         ImageChange::Removed => "removed",
+                                         ImageChange::Retagged => "retagged",
+                                     },
+                                     markdown_versions(&image_change.old_versions),
+                                     markdown_versions(&image_change.new_versions),
+                                     image_change.sources.join("`, `"));
+                }
+            }
+
+            if app.release_notes.change_count() > 0 || !app.release_notes.links.is_empty() {
+                markdown.push_str("\n### Release notes\n");
+                for (kind, release_notes) in app.release_notes.kinds().iter().filter(|(_, release_notes)| !release_notes.is_empty()) {
+                    let _ = writeln!(markdown, "\n**{}**\n", kind);
+                    for release_note in release_notes.iter() {
+                        let _ = writeln!(markdown, "- `{}`: {}{}", release_note.version, release_note.description, markdown_links(&release_note.links));
+                    }
+                }
+                if !app.release_notes.links.is_empty() {
+                    markdown.push_str("\n**Links**\n\n");
+                    for link in &app.release_notes.links {
+                        let _ = writeln!(markdown, "- [{}]({})", link.name, link.url);
+                    }
+                }
+            }
+
+            if let Some(chart_docs) = &app.chart_docs {
+                markdown.push_str(&chart_docs.to_markdown());
+            }
+            if let Some(chart_diff) = &app.chart_diff {
+                let _ = writeln!(markdown, "\n### Changed chart files\n\nThe full diff is in `{}`.\n", chart_diff.diff_file);
+                for file in &chart_diff.files {
+                    let change = match file.change {
+                        FileChange::Added => "added",
+                        FileChange::Removed => "removed",
+                        FileChange::Modified => "modified",
+                    };
+                    match &file.diff {
+                        Some(diff) => { let _ = writeln!(markdown, "- {} `{}`\n\n  <details><summary>diff</summary>\n\n  ```diff\n{}  ```\n  </details>\n", change, file.path, indent_markdown(diff)); }
+                        None => { let _ = writeln!(markdown, "- {} `{}`", change, file.path); }
+                    }
+                }
+            }
+        }
+
+        markdown
+    }
+}
+
+impl ChartDocsReport {
+    fn to_markdown(&self) -> String {
+        let mut markdown = String::new();
+        if let Some(changelog) = &self.changelog {
+            let _ = write!(markdown, "\n### Changelog\n\n<details><summary>New entries in CHANGELOG.md</summary>\n\n{}\n</details>\n", changelog);
+        }
+        if !self.parameter_changes.is_empty() {
+            markdown.push_str("\n### Changed parameters in README.md\n\n| Parameter | Change | Old row | New row |\n| --- | --- | --- | --- |\n");
+            for parameter_change in &self.parameter_changes {
+                let _ = writeln!(markdown, "| `{}` | {} | {} | {} |",
+                                 parameter_change.parameter,
+                                 match parameter_change.change {
+                                     DefaultChange::Added => "added",
+                                     DefaultChange::Removed => "removed",
+                                     DefaultChange::Changed => "changed",
+                                 },
+                                 parameter_change.old_row.as_deref().unwrap_or_default().replace('|', "\\|"),
+                                 parameter_change.new_row.as_deref().unwrap_or_default().replace('|', "\\|"));
+            }
+        }
+        if let Some(notes_diff) = &self.notes_diff {
+            let _ = write!(markdown, "\n### NOTES.txt\n\n```diff\n{}```\n", notes_diff);
+        }
+        markdown
+    }
+}
+
+// Keeps multi-line content inside a list item
+fn indent_markdown(text: &str) -> String {
+    text.lines().map(|line| format!("  {}\n", line)).collect()
+}
+
+fn markdown_links(links: &[LinkReport]) -> String {
+    links.iter().map(|link| format!(" ([{}]({}))", link.name, link.url)).collect()
+}
+
+fn markdown_versions(versions: &[String]) -> String {
+    versions.iter()
+        .map(|version| if version.is_empty() { String::from("unknown") } else { format!("`{}`", version) })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn markdown_value(value: &Option<serde_yaml::Value>) -> String {
+    match value {
+        Some(value) => format!("`{}`", crate::values::format_value(value).replace('|', "\\|")),
+        None => String::new(),
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::HashSet;
+use serde_yaml::Value;
+use anyhow::{Context, Result};
+use log::{debug, warn};
+
+use crate::values::{self, KeyPath};
+use crate::yaml_text::{self, split_key_line};
+
+// Reduces a values file that is a full copy of a chart's values.yaml to the keys that differ from the chart's
+// defaults. The file is filtered line by line to keep our comments, and the result is checked to give helm the
+// same values as the full file. If it doesn't, the overrides are written out as plain YAML without comments.
+
+/// Returns the slimmed down content of the values file `values_text`, given the chart's default values.
+pub fn slim_values_file(values_text: &str, defaults: &Value) -> Result<String> {
+    // Helm only reads the first document, the others are kept as they are
+    let (first_document, other_documents) = yaml_text::split_first_document(values_text);
+    let values = values::parse_values(first_document)
+        .with_context(|| "Couldn't parse the values file!")?;
+    let overrides = values::remove_defaults(&values, defaults);
+    let mut kept_key_paths: HashSet<KeyPath> = values::key_paths(&overrides).into_iter().collect();
+
+    // Aliases need their anchors, so the values that the anchors of kept aliases are on are kept as a whole
+    let anchors = yaml_text::find_anchors(first_document);
+    let mut slimmed_text = filter_lines(first_document, &values, &kept_key_paths);
+    loop {
+        let anchor_key_paths: Vec<&KeyPath> = slimmed_text.lines()
+            .flat_map(|line| yaml_text::anchors_and_aliases(line).1)
+            .filter_map(|alias| anchors.iter().find(|(anchor, _)| anchor == alias).map(|(_, key_path)| key_path))
+            .collect();
+        let mut added_key_path = false;
+        for anchor_key_path in anchor_key_paths {
+            let subtree_key_paths = values::get_key_path(&values, anchor_key_path).map(values::key_paths).unwrap_or_default().into_iter()
+                .map(|key_path| [anchor_key_path.as_slice(), &key_path].concat());
+            let ancestor_key_paths = (1..=anchor_key_path.len()).map(|len| anchor_key_path[..len].to_vec());
+            for key_path in ancestor_key_paths.chain(subtree_key_paths) {
+                added_key_path |= kept_key_paths.insert(key_path);
+            }
+        }
+        if !added_key_path {
+            break;
+        }
+        slimmed_text = filter_lines(first_document, &values, &kept_key_paths);
+    }
+
+    if let Some(slimmed_values) = parse_slimmed_values(&slimmed_text) {
+        if values::coalesce(defaults, &slimmed_values) == values::coalesce(defaults, &values) {
+            debug!("Slimmed down the values file from {} to {} lines.", values_text.lines().count(), slimmed_text.lines().count());
+            return Ok(slimmed_text + other_documents);
+        }
+    }
+
+    warn!("Couldn't keep the comments of the values file while slimming it down, writing only the overrides instead.");
+    if overrides.as_mapping().is_some_and(|overrides| overrides.is_empty()) {
+        return Ok(String::from(other_documents));
+    }
+    serde_yaml::to_string(&overrides)
+        .map(|overrides_text| overrides_text + other_documents)
+        .with_context(|| "Couldn't serialize the overrides!")
+}
+
+// A file with nothing but comments is as good as an empty one
+fn parse_slimmed_values(values_text: &str) -> Option<Value> {
+    let has_content = values_text.lines().any(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'));
+    if !has_content {
+        return Some(Value::Null);
+    }
+    values::parse_values(values_text).ok()
+}
+
+// A key line whose value spans the following lines, like a map or a list, or a multi-line string
+struct Block {
+    indent: usize,
+    keep: bool,
+}
+
+fn filter_lines(values_text: &str, values: &Value, kept_key_paths: &HashSet<KeyPath>) -> String {
+    let mut output: Vec<&str> = Vec::new();
+    // Comments and blank lines that belong to whatever comes next
+    let mut pending: Vec<&str> = Vec::new();
+    let mut parents: Vec<(usize, KeyPath)> = Vec::new();
+    let mut block: Option<Block> = None;
+
+    for line in values_text.lines() {
+        let trimmed_line = line.trim_start();
+        let indent = line.len() - trimmed_line.len();
+
+        if let Some(current_block) = &block {
+            let is_part_of_block = !trimmed_line.is_empty()
+                && (indent > current_block.indent || (indent == current_block.indent && (trimmed_line == "-" || trimmed_line.starts_with("- "))));
+            if trimmed_line.is_empty() {
+                pending.push(line);
+                continue;
+            }
+            if is_part_of_block {
+                if current_block.keep {
+                    flush(&mut output, &mut pending);
+                    output.push(line);
+                } else {
+                    pending.clear();
+                }
+                continue;
+            }
+            block = None;
+        }
+
+        if trimmed_line.is_empty() || trimmed_line.starts_with('#') {
+            pending.push(line);
+            continue;
+        }
+
+        let (key, rest) = match split_key_line(trimmed_line) {
+            Some(key_line) => key_line,
+            None => {
+                // Document markers and anything this simple filter doesn't understand are kept as they are
+                flush(&mut output, &mut pending);
+                output.push(line);
+                continue;
+            }
+        };
+
+        while parents.last().is_some_and(|(parent_indent, _)| *parent_indent >= indent) {
+            parents.pop();
+        }
+        let mut key_path = parents.last().map(|(_, parent_key_path)| parent_key_path.clone()).unwrap_or_default();
+        key_path.push(key);
+
+        // A merge key (`<<: *anchor`) goes wherever the mapping it merges into goes
+        let keep = kept_key_paths.contains(&key_path) || key_path.last().is_some_and(|key| key == "<<");
+        if keep {
+            flush(&mut output, &mut pending);
+            output.push(line);
+        } else {
+            drop_pending(&output, &mut pending);
+        }
+
+        let has_inline_value = !rest.is_empty() && !rest.starts_with('#');
+        let is_parent = !has_inline_value && values::get_key_path(values, &key_path).is_some_and(Value::is_mapping);
+        if is_parent && keep {
+            parents.push((indent, key_path));
+        } else {
+            block = Some(Block { indent, keep });
+        }
+    }
+    flush(&mut output, &mut pending);
+
+    while output.last().is_some_and(|line| line.trim().is_empty()) {
+        output.pop();
+    }
+    let mut slimmed_text = output.join("\n");
+    if !slimmed_text.is_empty() {
+        slimmed_text.push('\n');
+    }
+    slimmed_text
+}
+
+fn flush<'a>(output: &mut Vec<&'a str>, pending: &mut Vec<&'a str>) {
+    for line in pending.drain(..) {
+        let is_blank = line.trim().is_empty();
+        if is_blank && output.last().is_none_or(|last_line| last_line.trim().is_empty()) {
+            continue;
+        }
+        output.push(line);
+    }
+}
+
+// The comments right above a key that is dropped go with it, but the header of the file stays
+fn drop_pending(output: &[&str], pending: &mut Vec<&str>) {
+    if output.is_empty() {
+        if let Some(header_end) = pending.iter().position(|line| line.trim().is_empty()) {
+            pending.truncate(header_end + 1);
+            return;
+        }
+    }
+    pending.retain(|line| line.trim().is_empty());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defaults() -> Value {
+        serde_yaml::from_str("replicas: 1\nimage:\n  repository: nginx\n  tag: \"1.19\"\nresources: {}\n").unwrap()
+    }
+
+    #[test]
+    fn keeps_only_the_overrides_with_their_comments() {
+        let values_text = "replicas: 1\nimage:\n  repository: nginx\n  # pinned for the proxy\n  tag: \"1.17\"\nresources: {}\n";
+        assert_eq!(slim_values_file(values_text, &defaults()).unwrap(), "image:\n  # pinned for the proxy\n  tag: \"1.17\"\n");
+    }
+
+    #[test]
+    fn keeps_keys_the_chart_doesnt_have() {
+        let values_text = "replicas: 3\nextra:\n  enabled: true\n";
+        assert_eq!(slim_values_file(values_text, &defaults()).unwrap(), values_text);
+    }
+
+    #[test]
+    fn returns_nothing_for_a_full_copy_of_the_defaults() {
+        let values_text = "replicas: 1\nimage:\n  repository: nginx\n  tag: \"1.19\"\nresources: {}\n";
+        assert_eq!(slim_values_file(values_text, &defaults()).unwrap().trim(), "");
+    }
+
+    #[test]
+    fn keeps_the_anchors_of_kept_aliases() {
+        let values_text = "replicas: 1\nimage: &image\n  repository: nginx\n  tag: \"1.19\"\nsidecar:\n  image: *image\n  port: 8080\n";
+        assert_eq!(slim_values_file(values_text, &defaults()).unwrap(),
+                   "image: &image\n  repository: nginx\n  tag: \"1.19\"\nsidecar:\n  image: *image\n  port: 8080\n");
+    }
+
+    #[test]
+    fn keeps_the_documents_after_the_first() {
+        let values_text = "replicas: 3\n---\nreplicas: 1\n";
+        assert_eq!(slim_values_file(values_text, &defaults()).unwrap(), values_text);
+    }
+}
@@ -0,0 +1,524 @@
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+use anyhow::{Context, Result};
+use log::{debug, warn};
+
+use crate::archive;
+use crate::values::{self, KeyPath};
+use crate::yaml_text;
+
+// Umbrella charts ship the default values of their dependencies under `charts/`, as chart dirs or as nested chart
+// archives. Helm puts them under the dependency's alias or name when the dependency is enabled by its condition or
+// tags, and the umbrella chart's own values.yaml overrides them. hmum does the same to know the real defaults.
+
+/// Dependencies of dependencies of ... only go so deep in real charts
+pub const MAX_DEPTH: usize = 5;
+
+#[derive(Debug, Default, Deserialize)]
+struct ChartFile {
+    #[serde(default)]
+    name: String,
+    // Can be a number in YAML, like `1.16`
+    #[serde(default, rename = "appVersion")]
+    app_version: Option<Value>,
+    #[serde(default)]
+    dependencies: Vec<Dependency>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Dependency {
+    name: String,
+    #[serde(default)]
+    alias: Option<String>,
+    #[serde(default)]
+    condition: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+/// The default values of a chart, with the default values of its subcharts.
+#[derive(Debug, Clone)]
+pub struct ChartValues {
+    values_file_path: PathBuf,
+    values: Value,
+    app_version: Option<String>,
+    subcharts: Vec<Subchart>,
+}
+
+#[derive(Debug, Clone)]
+struct Subchart {
+    // The dependency's alias or else its name
+    key: String,
+    condition: Option<String>,
+    tags: Vec<String>,
+    chart_values: ChartValues,
+}
+
+/// Whether `path`, relative to a chart dir, is one of the files needed to load its values: the values.yaml, Chart.yaml
+/// and requirements.yaml of the chart and of its subcharts, and the archives of the packaged subcharts.
+pub fn is_chart_values_file(path: &Path) -> bool {
+    let components: Vec<String> = path.iter().map(|component| component.to_string_lossy().into_owned()).collect();
+    let (file_name, dirs) = match components.split_last() {
+        Some(split) => split,
+        None => return false,
+    };
+    let (subchart_dirs, is_archive) = match dirs.split_last() {
+        Some((last_dir, parent_dirs)) if file_name.ends_with(".tgz") && last_dir == "charts" => (parent_dirs, true),
+        _ => (dirs, false),
+    };
+
+    let is_in_subchart_dirs = subchart_dirs.len() % 2 == 0 && subchart_dirs.chunks(2).all(|subchart_dir| subchart_dir[0] == "charts");
+    is_in_subchart_dirs && (is_archive || file_name == "values.yaml" || file_name == "Chart.yaml" || file_name == "requirements.yaml")
+}
+
+/// Loads the default values of the chart extracted to `chart_dir_path`, extracting its packaged subcharts next to them.
+pub fn load_chart_values(chart_dir_path: &Path) -> Result<ChartValues> {
+    load(chart_dir_path, 0)
+}
+
+fn load(chart_dir_path: &Path, depth: usize) -> Result<ChartValues> {
+    let values_file_path = chart_dir_path.join("values.yaml");
+    let values = if values_file_path.exists() {
+        values::parse_values_file(&values_file_path)?
+    } else {
+        Value::Null
+    };
+    // An empty values.yaml is as good as one without any key
+    let values = if values.is_null() { Value::Mapping(Mapping::new()) } else { values };
+
+    let subcharts = if depth < MAX_DEPTH {
+        load_subcharts(chart_dir_path, depth)?
+    } else {
+        warn!("Chart `{}` is nested too deeply, so the values of its subcharts are ignored!", chart_dir_path.display());
+        Vec::new()
+    };
+
+    let app_version = read_chart_file(&chart_dir_path.join("Chart.yaml"))?.app_version
+        .and_then(|app_version| match app_version {
+            Value::String(app_version) => Some(app_version),
+            Value::Number(app_version) => Some(app_version.to_string()),
+            _ => None,
+        });
+
+    Ok(ChartValues { values_file_path, values, app_version, subcharts })
+}
+
+fn load_subcharts(chart_dir_path: &Path, depth: usize) -> Result<Vec<Subchart>> {
+    let charts_dir_path = chart_dir_path.join("charts");
+    if !charts_dir_path.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    // Helm 3 charts list their dependencies in the Chart.yaml, older ones in a requirements.yaml
+    let mut dependencies = read_chart_file(&chart_dir_path.join("Chart.yaml"))?.dependencies;
+    let requirements_file_path = chart_dir_path.join("requirements.yaml");
+    if requirements_file_path.exists() {
+        dependencies.extend(read_chart_file(&requirements_file_path)?.dependencies);
+    }
+
+    let mut subcharts = Vec::new();
+    for (subchart_name, subchart_dir_path) in find_subchart_dirs(&charts_dir_path)? {
+        let chart_values = match load(&subchart_dir_path, depth + 1) {
+            Ok(chart_values) => chart_values,
+            Err(e) => {
+                warn!("Couldn't load the values of subchart `{}` of chart `{}`, so they are ignored! {:#}", subchart_name, chart_dir_path.display(), e);
+                continue;
+            }
+        };
+
+        // The same chart can be a dependency several times under different aliases
+        let subchart_dependencies: Vec<&Dependency> = dependencies.iter().filter(|dependency| dependency.name == subchart_name).collect();
+        if subchart_dependencies.is_empty() {
+            subcharts.push(Subchart { key: subchart_name, condition: None, tags: Vec::new(), chart_values });
+            continue;
+        }
+        for dependency in subchart_dependencies {
+            subcharts.push(Subchart {
+                key: dependency.alias.clone().unwrap_or_else(|| dependency.name.clone()),
+                condition: dependency.condition.clone(),
+                tags: dependency.tags.clone(),
+                chart_values: chart_values.clone(),
+            });
+        }
+    }
+
+    debug!("Chart `{}` has {} subchart(s).", chart_dir_path.display(), subcharts.len());
+    Ok(subcharts)
+}
+
+// Returns the name and dir of every subchart in `charts_dir_path`, after extracting the packaged ones
+fn find_subchart_dirs(charts_dir_path: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let mut entry_paths: Vec<PathBuf> = fs::read_dir(charts_dir_path)
+        .with_context(|| format!("Couldn't read dir `{}`!", charts_dir_path.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        // The packaged subcharts are extracted to hidden dirs
+        .filter(|entry_path| !entry_path.file_name().is_some_and(|file_name| file_name.to_string_lossy().starts_with('.')))
+        .collect();
+    entry_paths.sort();
+
+    let mut subchart_dirs = Vec::new();
+    for entry_path in entry_paths {
+        let file_name = entry_path.file_name().unwrap().to_string_lossy().into_owned();
+        let subchart_dir_path = if entry_path.is_dir() {
+            entry_path
+        } else if let Some(archive_name) = file_name.strip_suffix(".tgz") {
+            // Chart and version can both contain dashes, so the name of the subchart comes from its Chart.yaml below
+            match extract_packaged_subchart(&entry_path, archive_name, is_chart_values_file) {
+                Ok(subchart_dir_path) => subchart_dir_path,
+                Err(e) => {
+                    warn!("Couldn't extract subchart archive `{}`, so its values are ignored! {:#}", entry_path.display(), e);
+                    continue;
+                }
+            }
+        } else {
+            continue;
+        };
+
+        let chart_file_path = subchart_dir_path.join("Chart.yaml");
+        if !chart_file_path.exists() {
+            continue;
+        }
+        match read_chart_file(&chart_file_path) {
+            Ok(chart_file) => subchart_dirs.push((chart_file.name, subchart_dir_path)),
+            Err(e) => warn!("Couldn't read the Chart.yaml of subchart `{}`, so its values are ignored! {:#}", subchart_dir_path.display(), e),
+        }
+    }
+
+    Ok(subchart_dirs)
+}
+
+/// Extracts the files for which `is_wanted` returns true from the packaged subchart at `archive_path`, a `<archive_name>.tgz`
+/// in a `charts/` dir, to a hidden dir next to it and returns the path of the extracted subchart dir.
+pub fn extract_packaged_subchart<F>(archive_path: &Path, archive_name: &str, is_wanted: F) -> Result<PathBuf>
+    where F: Fn(&Path) -> bool {
+    let extraction_path = archive_path.with_file_name(format!(".{}", archive_name));
+    archive::extract_unnamed_chart_files(archive_path, &extraction_path, is_wanted)
+}
+
+/// Returns the texts of the `original` and the `latest` chart's values.yaml for the line merge of `values_text`, with the
+/// default values of the subcharts that are enabled for `values` added under their keys, as far as `values_text` has
+/// them. That way the subcharts' values that were copied into a values file get their changes too.
+pub fn merge_base_texts(original: &ChartValues, latest: &ChartValues, values: &Value, values_text: &str) -> Result<(String, String)> {
+    let (our_document, _) = yaml_text::split_first_document(values_text);
+    let our_values = match values::parse_values(our_document) {
+        Ok(our_values) => our_values,
+        Err(_) => return Ok((read_values_text(&original.values_file_path)?, read_values_text(&latest.values_file_path)?)),
+    };
+
+    // Whether our file is a copy of a subchart's values is up to the version it was copied from
+    let copied_subchart_keys = original.copied_subchart_keys(values, &our_values);
+    Ok((original.merge_base_text(values, our_document, &our_values, &copied_subchart_keys)?,
+        latest.merge_base_text(values, our_document, &our_values, &copied_subchart_keys)?))
+}
+
+fn read_values_text(values_file_path: &Path) -> Result<String> {
+    if !values_file_path.exists() {
+        return Ok(String::new());
+    }
+    fs::read_to_string(values_file_path)
+        .with_context(|| format!("Could not open file `{}`", values_file_path.display()))
+}
+
+fn read_chart_file(chart_file_path: &Path) -> Result<ChartFile> {
+    if !chart_file_path.exists() {
+        return Ok(ChartFile::default());
+    }
+    let chart_file = File::open(chart_file_path)
+        .with_context(|| format!("Could not open file `{}`", chart_file_path.display()))?;
+    serde_yaml::from_reader(BufReader::new(chart_file))
+        .with_context(|| format!("Could not parse `{}`!", chart_file_path.display()))
+}
+
+impl ChartValues {
+    /// Returns the chart's default values, with the default values of the subcharts that are enabled for `values`
+    /// under their keys. `values` is what the app's values files set, which the conditions and tags are checked against.
+    pub fn effective_defaults(&self, values: &Value) -> Value {
+        let mut defaults = self.values.clone();
+        if self.subcharts.is_empty() {
+            return defaults;
+        }
+
+        let coalesced_values = values::coalesce(&self.values, values);
+        for subchart in self.subcharts.iter().filter(|subchart| subchart.is_enabled(&coalesced_values)) {
+            let key_path = std::slice::from_ref(&subchart.key);
+            let subchart_values = values::get_key_path(values, key_path).cloned().unwrap_or(Value::Null);
+            let subchart_defaults = subchart.chart_values.effective_defaults(&subchart_values);
+
+            let parent_defaults = values::get_key_path(&defaults, key_path).cloned().unwrap_or(Value::Null);
+            if let Value::Mapping(defaults_mapping) = &mut defaults {
+                defaults_mapping.insert(Value::String(subchart.key.clone()), values::coalesce(&subchart_defaults, &parent_defaults));
+            }
+        }
+
+        defaults
+    }
+
+    // Returns the keys of the enabled subcharts whose values `our_values` holds more than half of, as copies of their
+    // values.yaml do, rather than setting a few of them
+    fn copied_subchart_keys(&self, values: &Value, our_values: &Value) -> Vec<String> {
+        let coalesced_values = values::coalesce(&self.values, values);
+        self.subcharts.iter()
+            .filter(|subchart| subchart.is_enabled(&coalesced_values))
+            .filter(|subchart| match (&subchart.chart_values.values, values::get_key_path(our_values, std::slice::from_ref(&subchart.key))) {
+                (Value::Mapping(subchart_map), Some(Value::Mapping(our_map))) =>
+                    subchart_map.iter().filter(|(key, _)| our_map.contains_key(key)).count() * 2 > subchart_map.len(),
+                _ => false,
+            })
+            .map(|subchart| subchart.key.clone())
+            .collect()
+    }
+
+    // See `merge_base_texts`
+    fn merge_base_text(&self, values: &Value, our_document: &str, our_values: &Value, copied_subchart_keys: &[String]) -> Result<String> {
+        let text = read_values_text(&self.values_file_path)?;
+        if self.subcharts.is_empty() {
+            return Ok(text);
+        }
+
+        let lines: Vec<&str> = text.lines().collect();
+        let first_document_line_count = yaml_text::split_first_document(&text).0.lines().count();
+        let blocks = yaml_text::find_key_blocks(&lines[..first_document_line_count]);
+        let our_lines: Vec<&str> = our_document.lines().collect();
+        let our_blocks = yaml_text::find_key_blocks(&our_lines);
+        let our_keys: Vec<&String> = our_blocks.iter().filter(|block| block.key_path.len() == 1).map(|block| &block.key_path[0]).collect();
+
+        // Where to insert the subcharts' values and what to insert, the whole values.yaml of subcharts go after the
+        // values added to the block that ends there, in our order
+        let mut insertions: Vec<(usize, Option<usize>, Vec<String>)> = Vec::new();
+        let coalesced_values = values::coalesce(&self.values, values);
+        for subchart in self.subcharts.iter().filter(|subchart| subchart.is_enabled(&coalesced_values)) {
+            let key_path = std::slice::from_ref(&subchart.key);
+            let our_position = match our_keys.iter().position(|key| **key == subchart.key) {
+                Some(our_position) if values::get_key_path(our_values, key_path).is_some_and(Value::is_mapping) => our_position,
+                _ => continue,
+            };
+
+            let subchart_text = read_values_text(&subchart.chart_values.values_file_path)?;
+            let subchart_lines: Vec<&str> = yaml_text::split_first_document(&subchart_text).0.lines().collect();
+            let subchart_blocks = yaml_text::find_key_blocks(&subchart_lines);
+            // Indented like the keys under the subchart's key, in the chart's values.yaml or else in our file
+            let indent = [&blocks, &our_blocks].iter()
+                .find_map(|blocks| blocks.iter().find(|block| block.key_path.len() == 2 && block.key_path.starts_with(key_path)).map(|block| block.indent))
+                .unwrap_or_else(|| yaml_text::indent_unit(&our_blocks));
+            let indent_line = |line: &&str| if line.trim().is_empty() { String::new() } else { format!("{}{}", " ".repeat(indent), line) };
+
+            // All of the subchart's keys when our file is a copy of its values.yaml, and otherwise only the ones our file
+            // sets, so files that set a few of them don't look like they removed the others. The keys the chart's
+            // values.yaml has are there already.
+            let chart_map = values::get_key_path(&self.values, key_path).and_then(Value::as_mapping);
+            let is_copy = copied_subchart_keys.contains(&subchart.key);
+            let mut copied_lines = Vec::new();
+            for subchart_block in subchart_blocks.iter().filter(|subchart_block| subchart_block.key_path.len() == 1) {
+                let subchart_key = &subchart_block.key_path[0];
+                let is_ours = values::has_key_path(our_values, &[subchart.key.clone(), subchart_key.clone()]);
+                if !(is_copy || is_ours) || chart_map.is_some_and(|chart_map| chart_map.contains_key(&Value::String(subchart_key.clone()))) {
+                    continue;
+                }
+                if subchart_block.blank_line_before && !copied_lines.is_empty() {
+                    copied_lines.push(String::new());
+                }
+                copied_lines.extend(subchart_lines[subchart_block.start..subchart_block.end].iter().map(indent_line));
+            }
+            if copied_lines.is_empty() {
+                continue;
+            }
+
+            match blocks.iter().find(|block| block.key_path == key_path) {
+                // At the end of the block of the subchart's key in the chart's values.yaml
+                Some(block) => {
+                    if chart_map.is_some() && block.end > block.key_line + 1 {
+                        insertions.push((block.end, None, copied_lines));
+                    }
+                }
+                // Under the subchart's key, after the key that comes before it in our file
+                None => {
+                    let line_index = our_keys[..our_position].iter().rev()
+                        .find_map(|key| blocks.iter().find(|block| block.key_path.len() == 1 && block.key_path[0] == **key).map(|block| block.end))
+                        .unwrap_or_else(|| blocks.first().map_or(first_document_line_count, |block| block.start));
+                    // Blank lines between the keys are a difference of ours the merge should keep
+                    let mut block_lines = Vec::new();
+                    let blank_line_before = our_blocks.iter().any(|block| block.key_path == key_path && block.blank_line_before);
+                    if blank_line_before && line_index > 0 && !lines[line_index - 1].trim().is_empty() {
+                        block_lines.push(String::new());
+                    }
+                    block_lines.push(format!("{}:", subchart.key));
+                    block_lines.extend(copied_lines);
+                    insertions.push((line_index, Some(our_position), block_lines));
+                }
+            }
+        }
+        if insertions.is_empty() {
+            return Ok(text);
+        }
+
+        debug!("Added the values of {} subchart(s) to the values of `{}` for the merge.", insertions.len(), self.values_file_path.display());
+        insertions.sort_by_key(|(line_index, our_position, _)| (*line_index, *our_position));
+        let mut base_lines: Vec<String> = Vec::new();
+        let mut insertions = insertions.into_iter().peekable();
+        for line_index in 0..=lines.len() {
+            while let Some((_, _, block_lines)) = insertions.next_if(|(insertion_index, _, _)| *insertion_index == line_index) {
+                base_lines.extend(block_lines);
+            }
+            if let Some(line) = lines.get(line_index) {
+                base_lines.push(String::from(*line));
+            }
+        }
+        Ok(base_lines.iter().map(|line| format!("{}\n", line)).collect())
+    }
+
+    /// Returns the appVersion of the chart or subchart whose values `key_path` is in, which is what image tags
+    /// usually default to.
+    pub fn app_version(&self, key_path: &[String]) -> Option<&str> {
+        key_path.split_first()
+            .and_then(|(key, sub_key_path)| self.subcharts.iter().find(|subchart| subchart.key == *key).map(|subchart| (subchart, sub_key_path)))
+            .map_or(self.app_version.as_deref(), |(subchart, sub_key_path)| subchart.chart_values.app_version(sub_key_path))
+    }
+}
+
+impl Subchart {
+    // Like helm: the first condition path that is set decides, then the tags that are set, and otherwise it's enabled
+    fn is_enabled(&self, values: &Value) -> bool {
+        for condition_path in self.condition.iter().flat_map(|condition| condition.split(',')) {
+            let key_path: KeyPath = condition_path.trim().split('.').map(String::from).collect();
+            if let Some(Value::Bool(enabled)) = values::get_key_path(values, &key_path) {
+                return *enabled;
+            }
+        }
+
+        let tags: Vec<bool> = self.tags.iter()
+            .filter_map(|tag| match values::get_key_path(values, &[String::from("tags"), tag.clone()]) {
+                Some(Value::Bool(enabled)) => Some(*enabled),
+                _ => None,
+            })
+            .collect();
+        tags.is_empty() || tags.contains(&true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use tar::{Builder, Header};
+    use tempfile::TempDir;
+
+    fn write_chart_archive(archive_path: &Path, files: &[(&str, &str)]) {
+        let mut builder = Builder::new(GzEncoder::new(File::create(archive_path).unwrap(), Compression::fast()));
+        for (path, content) in files {
+            let mut header = Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, content.as_bytes()).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+
+    fn write_file(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    // An umbrella chart with `common` under the alias `cache`, `redis` enabled by a condition and `metrics` by a tag
+    fn write_umbrella_chart(chart_dir_path: &Path) {
+        write_file(&chart_dir_path.join("Chart.yaml"), "name: umbrella\ndependencies:\n  - name: common\n    alias: cache\n  - name: redis\n    condition: redis.enabled\n  - name: metrics\n    tags:\n      - monitoring\n");
+        write_file(&chart_dir_path.join("values.yaml"), "redis:\n  enabled: true\n  password: \"\"\n");
+        write_file(&chart_dir_path.join("charts/common/Chart.yaml"), "name: common\n");
+        write_file(&chart_dir_path.join("charts/common/values.yaml"), "ttl: 60\nmode: lru\n");
+        write_file(&chart_dir_path.join("charts/redis/Chart.yaml"), "name: redis\n");
+        write_file(&chart_dir_path.join("charts/redis/values.yaml"), "image:\n  tag: \"6.0\"\nport: 6379\n");
+        write_file(&chart_dir_path.join("charts/metrics/Chart.yaml"), "name: metrics\n");
+        write_file(&chart_dir_path.join("charts/metrics/values.yaml"), "interval: 30s\n");
+    }
+
+    fn parse(values_text: &str) -> Value {
+        values::parse_values(values_text).unwrap()
+    }
+
+    #[test]
+    fn puts_subchart_defaults_under_their_alias_below_the_chart_defaults() {
+        let dir = TempDir::new().unwrap();
+        write_umbrella_chart(dir.path());
+        let chart_values = load_chart_values(dir.path()).unwrap();
+
+        let defaults = chart_values.effective_defaults(&Value::Null);
+        assert_eq!(defaults, parse("redis:\n  enabled: true\n  password: \"\"\n  image:\n    tag: \"6.0\"\n  port: 6379\ncache:\n  ttl: 60\n  mode: lru\nmetrics:\n  interval: 30s\n"));
+        assert!(values::get_key_path(&defaults, &[String::from("common")]).is_none());
+    }
+
+    #[test]
+    fn leaves_out_subcharts_disabled_by_their_condition_or_tags() {
+        let dir = TempDir::new().unwrap();
+        write_umbrella_chart(dir.path());
+        let chart_values = load_chart_values(dir.path()).unwrap();
+
+        let defaults = chart_values.effective_defaults(&parse("redis:\n  enabled: false\ntags:\n  monitoring: false\n"));
+        assert_eq!(defaults, parse("redis:\n  enabled: true\n  password: \"\"\ncache:\n  ttl: 60\n  mode: lru\n"));
+    }
+
+    #[test]
+    fn enables_subcharts_like_helm() {
+        let subchart = |condition: Option<&str>, tags: &[&str]| Subchart {
+            key: String::from("redis"),
+            condition: condition.map(String::from),
+            tags: tags.iter().map(|tag| String::from(*tag)).collect(),
+            chart_values: ChartValues { values_file_path: PathBuf::new(), values: Value::Null, app_version: None, subcharts: Vec::new() },
+        };
+
+        assert!(subchart(None, &[]).is_enabled(&Value::Null));
+        // The first condition path that is set decides, and over the tags
+        let condition = Some("cache.redis.enabled,redis.enabled");
+        assert!(!subchart(condition, &[]).is_enabled(&parse("redis:\n  enabled: false\n")));
+        assert!(subchart(condition, &[]).is_enabled(&parse("cache:\n  redis:\n    enabled: true\nredis:\n  enabled: false\n")));
+        assert!(subchart(condition, &["backend"]).is_enabled(&parse("redis:\n  enabled: true\ntags:\n  backend: false\n")));
+        assert!(subchart(condition, &[]).is_enabled(&parse("redis:\n  enabled: \"no\"\n")));
+        // One tag that is set to true is enough
+        assert!(!subchart(None, &["backend"]).is_enabled(&parse("tags:\n  backend: false\n")));
+        assert!(subchart(None, &["backend", "cache"]).is_enabled(&parse("tags:\n  backend: false\n  cache: true\n")));
+    }
+
+    #[test]
+    fn adds_the_subcharts_our_file_copied_to_the_merge_bases() {
+        let dir = TempDir::new().unwrap();
+        let (original_dir_path, latest_dir_path) = (dir.path().join("original"), dir.path().join("latest"));
+        write_umbrella_chart(&original_dir_path);
+        write_umbrella_chart(&latest_dir_path);
+        write_file(&latest_dir_path.join("charts/redis/values.yaml"), "image:\n  tag: \"7.0\"\nservicePort: 6379\n");
+        write_file(&latest_dir_path.join("charts/common/values.yaml"), "ttl: 120\nmode: lru\n");
+        let (original, latest) = (load_chart_values(&original_dir_path).unwrap(), load_chart_values(&latest_dir_path).unwrap());
+        let merge_base_texts = |values_text: &str| merge_base_texts(&original, &latest, &parse(values_text), values_text).unwrap();
+
+        // A copy of redis' values gets all of them, including the renamed key, a few values of common only those
+        let values_text = "redis:\n  enabled: true\n  password: secret\n  image:\n    tag: \"6.0\"\n  port: 6380\ncache:\n    mode: lfu\n";
+        assert_eq!(merge_base_texts(values_text), (
+            String::from("redis:\n  enabled: true\n  password: \"\"\n  image:\n    tag: \"6.0\"\n  port: 6379\ncache:\n    mode: lru\n"),
+            String::from("redis:\n  enabled: true\n  password: \"\"\n  image:\n    tag: \"7.0\"\n  servicePort: 6379\ncache:\n    mode: lru\n"),
+        ));
+        // In our order, the key that comes before it in our file being missing from the chart's values.yaml
+        let values_text = "metrics:\n  interval: 10s\nredis:\n  enabled: true\n";
+        assert_eq!(merge_base_texts(values_text).0, "metrics:\n  interval: 30s\nredis:\n  enabled: true\n  password: \"\"\n");
+        // Nothing to add for the values of disabled subcharts
+        let values_text = "redis:\n  enabled: false\n  port: 6380\n";
+        assert_eq!(merge_base_texts(values_text).0, "redis:\n  enabled: true\n  password: \"\"\n");
+    }
+
+    #[test]
+    fn names_packaged_subcharts_after_their_chart_file() {
+        let dir = TempDir::new().unwrap();
+        write_chart_archive(&dir.path().join("my-sub-chart-1.0.0-rc-1.tgz"), &[
+            ("my-sub-chart/Chart.yaml", "name: my-sub-chart\n"),
+            ("my-sub-chart/values.yaml", "replicas: 1\n"),
+        ]);
+        fs::create_dir(dir.path().join("broken")).unwrap();
+        fs::write(dir.path().join("broken/Chart.yaml"), "name: [\n").unwrap();
+
+        let subchart_dirs = find_subchart_dirs(dir.path()).unwrap();
+        assert_eq!(subchart_dirs.len(), 1);
+        assert_eq!(subchart_dirs[0].0, "my-sub-chart");
+        assert!(subchart_dirs[0].1.join("values.yaml").exists());
+    }
+}
@@ -0,0 +1,37 @@
+use std::path::Path;
+use std::process::Command;
+use anyhow::{Context, Result};
+use log::debug;
+
+// Unified diffs of chart files for the reports, made by `git diff --no-index`, since hmum needs git anyway.
+
+/// Returns the unified diff between two files, labelled with `label` instead of their paths, or `None` if they are
+/// the same. A missing file counts as empty.
+pub fn unified_diff(original_path: Option<&Path>, latest_path: Option<&Path>, label: &str) -> Result<Option<String>> {
+    debug!("Attempting to diff `{}`.", label);
+
+    let empty_path = Path::new("/dev/null");
+    let output = Command::new("git")
+        .arg("diff").arg("--no-index").arg("--no-color").arg("--no-ext-diff").arg("--")
+        .arg(original_path.unwrap_or(empty_path))
+        .arg(latest_path.unwrap_or(empty_path))
+        .output()
+        .with_context(|| format!("Error happened while diffing `{}`!", label))?;
+
+    // The exit code is 1 when the files differ
+    match output.status.code() {
+        Some(0) => return Ok(None),
+        Some(1) => {}
+        _ => return Err(anyhow::anyhow!("`git diff` failed with `{}`: {}", output.status, String::from_utf8_lossy(&output.stderr).trim())),
+    }
+
+    // The headers name the temporary files, so they are replaced
+    let diff = String::from_utf8_lossy(&output.stdout);
+    let hunks: String = diff.split_inclusive('\n').skip_while(|line| !line.starts_with("@@")).collect();
+    if hunks.is_empty() {
+        return Ok(Some(format!("Binary file {} differs\n", label)));
+    }
+    let original_label = if original_path.is_some() { format!("a/{}", label) } else { String::from("/dev/null") };
+    let latest_label = if latest_path.is_some() { format!("b/{}", label) } else { String::from("/dev/null") };
+    Ok(Some(format!("--- {}\n+++ {}\n{}", original_label, latest_label, hunks)))
+}
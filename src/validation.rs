@@ -0,0 +1,94 @@
+use std::fs;
+use std::path::Path;
+use serde_yaml::Value;
+use anyhow::{Context, Result};
+use log::debug;
+
+use crate::values::{self, KeyPath};
+
+// Helm refuses to install a chart when the values don't match the chart's values.schema.json, so a merged values
+// file is checked against it right away rather than when it is deployed. Like helm, the schema is applied to the
+// chart's default values with the values file on top of them.
+
+#[derive(Debug)]
+pub struct SchemaViolation {
+    pub key_path: KeyPath,
+    pub message: String,
+}
+
+/// Validates `values` coalesced over the chart's `defaults` against the JSON schema in `schema_path`.
+pub fn validate_values(values: &Value, defaults: &Value, schema_path: &Path) -> Result<Vec<SchemaViolation>> {
+    debug!("Attempting to validate values against the schema `{}`.", schema_path.display());
+
+    let schema_content = fs::read_to_string(schema_path)
+        .with_context(|| format!("Could not open file `{}`", schema_path.display()))?;
+    let schema: serde_json::Value = serde_json::from_str(&schema_content)
+        .with_context(|| format!("Could not parse the schema `{}`!", schema_path.display()))?;
+    let validator = jsonschema::validator_for(&schema)
+        .map_err(|e| anyhow::anyhow!("{}", e))
+        .with_context(|| format!("The schema `{}` is not a valid JSON schema!", schema_path.display()))?;
+
+    let coalesced_values = serde_json::to_value(values::coalesce(defaults, values))
+        .with_context(|| "Couldn't convert the values to JSON!")?;
+
+    let schema_violations: Vec<SchemaViolation> = validator.iter_errors(&coalesced_values)
+        .map(|error| SchemaViolation {
+            key_path: pointer_to_key_path(error.instance_path.as_str()),
+            message: error.to_string(),
+        })
+        .collect();
+
+    debug!("Found {} schema violation(s).", schema_violations.len());
+    Ok(schema_violations)
+}
+
+// `/image/pull~1secrets/0` is the JSON pointer of key path `image.pull/secrets.0`
+fn pointer_to_key_path(pointer: &str) -> KeyPath {
+    pointer.split('/')
+        .skip(1)
+        .map(|key| key.replace("~1", "/").replace("~0", "~"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const SCHEMA: &str = r#"{"type": "object", "required": ["image"], "properties": {"image": {"type": "object", "properties": {"tag": {"type": "string"}}}, "replicas": {"type": "integer"}}}"#;
+
+    fn validate(values_text: &str) -> Vec<SchemaViolation> {
+        let dir = TempDir::new().unwrap();
+        let schema_path = dir.path().join("values.schema.json");
+        fs::write(&schema_path, SCHEMA).unwrap();
+        let defaults = values::parse_values("replicas: 1\n").unwrap();
+        validate_values(&values::parse_values(values_text).unwrap(), &defaults, &schema_path).unwrap()
+    }
+
+    #[test]
+    fn passes_valid_values() {
+        assert!(validate("image:\n  tag: \"1.0\"\n").is_empty());
+    }
+
+    #[test]
+    fn reports_missing_required_keys_at_the_top_level() {
+        let schema_violations = validate("replicas: 2\n");
+        assert_eq!(schema_violations.len(), 1);
+        assert!(schema_violations[0].key_path.is_empty());
+        assert!(schema_violations[0].message.contains("image"));
+    }
+
+    #[test]
+    fn reports_type_mismatches_with_their_key_path() {
+        let schema_violations = validate("image:\n  tag: 1.0\nreplicas: many\n");
+        let key_paths: Vec<String> = schema_violations.iter().map(|schema_violation| schema_violation.key_path.join(".")).collect();
+        assert_eq!(key_paths.len(), 2);
+        assert!(key_paths.contains(&String::from("image.tag")) && key_paths.contains(&String::from("replicas")));
+    }
+
+    #[test]
+    fn unescapes_json_pointers() {
+        assert_eq!(pointer_to_key_path("/image/pull~1secrets/0"), vec!["image", "pull/secrets", "0"]);
+        assert!(pointer_to_key_path("").is_empty());
+    }
+}
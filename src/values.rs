@@ -0,0 +1,523 @@
+use std::fs;
+use std::path::Path;
+use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+use anyhow::{Context, Result};
+use log::debug;
+
+// Helpers to compare values files by their key paths, like `image.tag`, rather than line by line.
+// Sequences are treated as single values, since helm replaces them as a whole instead of merging them.
+
+pub type KeyPath = Vec<String>;
+
+/// Parses every YAML document in `values_text`. Aliases are resolved by the parser and merge keys (`<<: *anchor`)
+/// are applied here, so the values are the ones helm sees.
+pub fn parse_documents(values_text: &str) -> Result<Vec<Value>> {
+    serde_yaml::Deserializer::from_str(values_text)
+        .map(|document| Value::deserialize(document).map(apply_merge_keys))
+        .collect::<Result<Vec<Value>, _>>()
+        .with_context(|| "Could not parse yaml!")
+}
+
+/// Parses a values file like helm does, which only reads the first YAML document of it.
+pub fn parse_values(values_text: &str) -> Result<Value> {
+    let documents = parse_documents(values_text)?;
+    if documents.len() > 1 {
+        debug!("Only the first of {} YAML documents is used, like helm does.", documents.len());
+    }
+    Ok(documents.into_iter().next().unwrap_or(Value::Null))
+}
+
+/// Reads and parses a values file with `parse_values`.
+pub fn parse_values_file(file_path: &Path) -> Result<Value> {
+    debug!("Attempting to parse values file `{}`.", file_path.display());
+
+    let values_text = fs::read_to_string(file_path)
+        .with_context(|| format!("Could not open file `{}`", file_path.display()))?;
+    let values = parse_values(&values_text)
+        .with_context(|| format!("Could not parse values file `{}`!", file_path.display()))?;
+
+    debug!("Values file `{}` was parsed successfully!", file_path.display());
+    Ok(values)
+}
+
+// The keys of the mappings under `<<` are added to the mapping, unless it sets them itself or an earlier mapping did
+fn apply_merge_keys(value: Value) -> Value {
+    match value {
+        Value::Mapping(mapping) => {
+            let merge_key = Value::String(String::from("<<"));
+            let mut merged_mapping = Mapping::new();
+            for (key, value) in mapping.iter() {
+                if *key != merge_key {
+                    merged_mapping.insert(key.clone(), apply_merge_keys(value.clone()));
+                    continue;
+                }
+
+                let merged_values = match value {
+                    Value::Sequence(sequence) => sequence.clone(),
+                    value => vec![value.clone()],
+                };
+                for merged_value in merged_values {
+                    if let Value::Mapping(merged_value_mapping) = apply_merge_keys(merged_value) {
+                        for (merged_key, merged_value) in merged_value_mapping {
+                            if !mapping.contains_key(&merged_key) && !merged_mapping.contains_key(&merged_key) {
+                                merged_mapping.insert(merged_key, merged_value);
+                            }
+                        }
+                    }
+                }
+            }
+            Value::Mapping(merged_mapping)
+        }
+        Value::Sequence(sequence) => Value::Sequence(sequence.into_iter().map(apply_merge_keys).collect()),
+        value => value,
+    }
+}
+
+/// Joins the keys with `.`, quoting the keys that contain a `.` or a `"`, like `podAnnotations."prometheus.io/scrape"`.
+pub fn format_key_path(key_path: &[String]) -> String {
+    key_path.iter()
+        .map(|key| if key.contains('.') || key.contains('"') {
+            format!("\"{}\"", key.replace('\\', "\\\\").replace('"', "\\\""))
+        } else {
+            key.clone()
+        })
+        .collect::<Vec<String>>()
+        .join(".")
+}
+
+/// Returns the path of every key in `values`, parents before their children, in the order of the file.
+pub fn key_paths(values: &Value) -> Vec<KeyPath> {
+    let mut key_paths = Vec::new();
+    collect_key_paths(values, &mut Vec::new(), &mut key_paths);
+    key_paths
+}
+
+fn collect_key_paths(values: &Value, parent_key_path: &mut KeyPath, key_paths: &mut Vec<KeyPath>) {
+    if let Value::Mapping(mapping) = values {
+        for (key, value) in mapping {
+            parent_key_path.push(key_to_string(key));
+            key_paths.push(parent_key_path.clone());
+            collect_key_paths(value, parent_key_path, key_paths);
+            parent_key_path.pop();
+        }
+    }
+}
+
+pub fn get_key_path<'v>(values: &'v Value, key_path: &[String]) -> Option<&'v Value> {
+    key_path.iter().try_fold(values, |value, key| {
+        value.as_mapping()?
+            .iter()
+            .find(|(mapping_key, _)| key_to_string(mapping_key) == *key)
+            .map(|(_, mapping_value)| mapping_value)
+    })
+}
+
+pub fn has_key_path(values: &Value, key_path: &[String]) -> bool {
+    get_key_path(values, key_path).is_some()
+}
+
+/// Matches a key path against a pattern like `image.tag`, `*.apiVersion` or `resources.**`: `*` stands for any
+/// single key or part of one and `**` for any number of keys. Keys in double quotes, like
+/// `podAnnotations."prometheus.io/scrape"`, may contain dots and are matched literally.
+pub fn key_path_matches(pattern: &str, key_path: &[String]) -> bool {
+    keys_match(&parse_pattern_keys(pattern), key_path)
+}
+
+enum PatternKey {
+    Glob(String),
+    Literal(String),
+}
+
+// Splits on the dots outside of double quotes, `\` escapes the next character inside them
+fn parse_pattern_keys(pattern: &str) -> Vec<PatternKey> {
+    let to_pattern_key = |key: String, literal: bool| if literal { PatternKey::Literal(key) } else { PatternKey::Glob(key) };
+    let mut pattern_keys = Vec::new();
+    let mut key = String::new();
+    let (mut literal, mut quoted) = (false, false);
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if !quoted && key.is_empty() && !literal => {
+                literal = true;
+                quoted = true;
+            }
+            '"' if quoted => quoted = false,
+            '\\' if quoted => key.extend(chars.next()),
+            '.' if !quoted => {
+                pattern_keys.push(to_pattern_key(std::mem::take(&mut key), literal));
+                literal = false;
+            }
+            c => key.push(c),
+        }
+    }
+    pattern_keys.push(to_pattern_key(key, literal));
+    pattern_keys
+}
+
+fn keys_match(pattern_keys: &[PatternKey], keys: &[String]) -> bool {
+    match pattern_keys.split_first() {
+        None => keys.is_empty(),
+        Some((PatternKey::Glob(pattern_key), rest_pattern_keys)) if pattern_key == "**" =>
+            (0..=keys.len()).any(|skipped| keys_match(rest_pattern_keys, &keys[skipped..])),
+        Some((pattern_key, rest_pattern_keys)) => match keys.split_first() {
+            Some((key, rest_keys)) => {
+                let key_matched = match pattern_key {
+                    PatternKey::Glob(pattern_key) => key_matches(pattern_key, key),
+                    PatternKey::Literal(literal_key) => literal_key == key,
+                };
+                key_matched && keys_match(rest_pattern_keys, rest_keys)
+            }
+            None => false,
+        },
+    }
+}
+
+fn key_matches(pattern_key: &str, key: &str) -> bool {
+    match pattern_key.split_once('*') {
+        None => pattern_key == key,
+        Some((prefix, rest_pattern_key)) => key.strip_prefix(prefix)
+            .is_some_and(|rest_key| (0..=rest_key.len()).filter(|i| rest_key.is_char_boundary(*i)).any(|i| key_matches(rest_pattern_key, &rest_key[i..]))),
+    }
+}
+
+/// Keeps only the outermost key paths, e.g. `image` but not `image.tag` as well.
+pub fn outermost_key_paths(key_paths: Vec<KeyPath>) -> Vec<KeyPath> {
+    let mut outermost_key_paths: Vec<KeyPath> = Vec::new();
+    for key_path in key_paths {
+        if !outermost_key_paths.iter().any(|outer_key_path| key_path.starts_with(outer_key_path)) {
+            outermost_key_paths.push(key_path);
+        }
+    }
+    outermost_key_paths
+}
+
+/// Keeps only the innermost key paths, e.g. `image.tag` but not `image` as well.
+pub fn innermost_key_paths(key_paths: Vec<KeyPath>) -> Vec<KeyPath> {
+    key_paths.iter()
+        .filter(|key_path| !key_paths.iter().any(|inner_key_path| inner_key_path.len() > key_path.len() && inner_key_path.starts_with(key_path)))
+        .cloned()
+        .collect()
+}
+
+/// A key set in our values file that doesn't exist in the latest chart version, which helm silently ignores.
+#[derive(Debug)]
+pub struct OrphanedOverride {
+    pub key_path: KeyPath,
+    // A similarly named key that appeared next to it in the latest chart version
+    pub suggestion: Option<String>,
+}
+
+/// Finds the keys `values` overrides, i.e. sets to something else than the original chart's values.yaml does,
+/// that don't exist in the latest chart's values.yaml. Keys under a map that is empty upstream, like `resources: {}`,
+/// are free-form and never orphaned.
+pub fn find_orphaned_overrides(values: &Value, original_values: &Value, latest_values: &Value) -> Vec<OrphanedOverride> {
+    // Only the values themselves count, a map that leaves out some of the original keys doesn't override anything.
+    // An orphaned value is reported at the outermost of its keys that the latest chart doesn't have.
+    let orphaned_key_paths = key_paths(values).into_iter()
+        .filter(|key_path| !matches!(get_key_path(values, key_path), Some(Value::Mapping(mapping)) if !mapping.is_empty()))
+        .filter(|key_path| get_key_path(values, key_path) != get_key_path(original_values, key_path))
+        .filter(|key_path| !has_key_path(latest_values, key_path) && is_defined_upstream(latest_values, key_path))
+        .filter_map(|key_path| (1..=key_path.len()).map(|len| key_path[..len].to_vec()).find(|key_path| !has_key_path(latest_values, key_path)))
+        .collect();
+
+    outermost_key_paths(orphaned_key_paths).into_iter()
+        .map(|key_path| {
+            let suggestion = suggest_rename(&key_path, values, original_values, latest_values);
+            OrphanedOverride { key_path, suggestion }
+        })
+        .collect()
+}
+
+// Whether the closest parent of `key_path` in the latest values is a map with a fixed set of keys
+fn is_defined_upstream(latest_values: &Value, key_path: &[String]) -> bool {
+    let closest_parent = (0..key_path.len()).rev()
+        .find_map(|parent_len| get_key_path(latest_values, &key_path[..parent_len]));
+
+    matches!(closest_parent, Some(Value::Mapping(mapping)) if !mapping.is_empty())
+}
+
+fn suggest_rename(key_path: &[String], values: &Value, original_values: &Value, latest_values: &Value) -> Option<String> {
+    let (key, parent_key_path) = key_path.split_last()?;
+    let sibling_keys = |values: &Value| -> Vec<String> {
+        match get_key_path(values, parent_key_path) {
+            Some(Value::Mapping(mapping)) => mapping.iter().map(|(sibling_key, _)| key_to_string(sibling_key)).collect(),
+            _ => Vec::new(),
+        }
+    };
+    let original_keys = sibling_keys(original_values);
+    let our_keys = sibling_keys(values);
+
+    sibling_keys(latest_values).into_iter()
+        .filter(|new_key| !original_keys.contains(new_key) && !our_keys.contains(new_key))
+        .map(|new_key| (similarity(key, &new_key), new_key))
+        .filter(|(similarity, _)| *similarity >= 0.5)
+        .max_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap())
+        .map(|(_, new_key)| {
+            let mut renamed_key_path = parent_key_path.to_vec();
+            renamed_key_path.push(new_key);
+            format_key_path(&renamed_key_path)
+        })
+}
+
+// 1.0 for names that only differ in case, 0.0 for completely different ones
+fn similarity(a: &str, b: &str) -> f64 {
+    let a: Vec<char> = a.to_lowercase().chars().collect();
+    let b: Vec<char> = b.to_lowercase().chars().collect();
+    let max_len = a.len().max(b.len());
+    if max_len == 0 {
+        return 1.0;
+    }
+
+    let (shorter, longer) = if a.len() <= b.len() { (&a, &b) } else { (&b, &a) };
+    if shorter.len() >= 3 && longer.windows(shorter.len()).any(|window| window == shorter.as_slice()) {
+        return 0.75_f64.max(shorter.len() as f64 / max_len as f64);
+    }
+
+    1.0 - levenshtein_distance(&a, &b) as f64 / max_len as f64
+}
+
+fn levenshtein_distance(a: &[char], b: &[char]) -> usize {
+    let mut previous_row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.iter().enumerate() {
+        let mut current_row = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution_cost = if a_char == b_char { 0 } else { 1 };
+            current_row.push((previous_row[j] + substitution_cost).min(previous_row[j + 1] + 1).min(current_row[j] + 1));
+        }
+        previous_row = current_row;
+    }
+    previous_row[b.len()]
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ValueChange {
+    Added(Value),
+    Removed(Value),
+    Changed(Value, Value),
+}
+
+/// Compares two values files key by key. Keys that were added or removed as a whole map are reported once,
+/// without their children.
+pub fn diff_values(original_values: &Value, latest_values: &Value) -> Vec<(KeyPath, ValueChange)> {
+    let removed_key_paths = outermost_key_paths(key_paths(original_values).into_iter()
+        .filter(|key_path| !has_key_path(latest_values, key_path))
+        .collect());
+    let added_key_paths = outermost_key_paths(key_paths(latest_values).into_iter()
+        .filter(|key_path| !has_key_path(original_values, key_path))
+        .collect());
+
+    let mut changes: Vec<(KeyPath, ValueChange)> = Vec::new();
+    for key_path in key_paths(latest_values) {
+        match (get_key_path(original_values, &key_path), get_key_path(latest_values, &key_path)) {
+            (Some(Value::Mapping(_)), Some(Value::Mapping(_))) => {}
+            (Some(original_value), Some(latest_value)) if original_value != latest_value => {
+                changes.push((key_path, ValueChange::Changed(original_value.clone(), latest_value.clone())));
+            }
+            _ => {}
+        }
+    }
+    for key_path in removed_key_paths {
+        let original_value = get_key_path(original_values, &key_path).cloned().unwrap_or(Value::Null);
+        changes.push((key_path, ValueChange::Removed(original_value)));
+    }
+    for key_path in added_key_paths {
+        let latest_value = get_key_path(latest_values, &key_path).cloned().unwrap_or(Value::Null);
+        changes.push((key_path, ValueChange::Added(latest_value)));
+    }
+
+    changes
+}
+
+/// Removes everything from `values` that is the same as in `defaults`, which leaves the real overrides.
+pub fn remove_defaults(values: &Value, defaults: &Value) -> Value {
+    match (values, defaults) {
+        (Value::Mapping(mapping), Value::Mapping(default_mapping)) => {
+            let mut overrides = Mapping::new();
+            for (key, value) in mapping {
+                match (value, default_mapping.get(key)) {
+                    (value, Some(default_value)) if value == default_value => {}
+                    (Value::Mapping(_), Some(default_value @ Value::Mapping(_))) => {
+                        let nested_overrides = remove_defaults(value, default_value);
+                        if nested_overrides.as_mapping().is_some_and(|nested_overrides| !nested_overrides.is_empty()) {
+                            overrides.insert(key.clone(), nested_overrides);
+                        }
+                    }
+                    (value, _) => {
+                        overrides.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+            Value::Mapping(overrides)
+        }
+        (values, _) => values.clone(),
+    }
+}
+
+/// Merges `values` over `defaults` the way helm does: maps are merged key by key, everything else is replaced
+/// and a `null` removes the default.
+pub fn coalesce(defaults: &Value, values: &Value) -> Value {
+    match (defaults, values) {
+        (Value::Mapping(default_mapping), Value::Mapping(mapping)) => {
+            let mut coalesced = default_mapping.clone();
+            for (key, value) in mapping {
+                match (value, default_mapping.get(key)) {
+                    (Value::Null, _) => { coalesced.remove(key); }
+                    (value, Some(default_value)) => { coalesced.insert(key.clone(), coalesce(default_value, value)); }
+                    (value, None) => { coalesced.insert(key.clone(), value.clone()); }
+                }
+            }
+            Value::Mapping(coalesced)
+        }
+        (defaults, Value::Null) => defaults.clone(),
+        (_, values) => values.clone(),
+    }
+}
+
+/// Formats a value on a single line, for console output and reports.
+pub fn format_value(value: &Value) -> String {
+    serde_json::to_string(value).unwrap_or_else(|_| serde_yaml::to_string(value).unwrap_or_default().trim().to_string())
+}
+
+fn key_to_string(key: &Value) -> String {
+    match key {
+        Value::String(key) => key.clone(),
+        Value::Number(key) => key.to_string(),
+        Value::Bool(key) => key.to_string(),
+        Value::Null => String::from("null"),
+        other => serde_yaml::to_string(other).unwrap_or_default().trim().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_path(keys: &[&str]) -> KeyPath {
+        keys.iter().map(|key| String::from(*key)).collect()
+    }
+
+    #[test]
+    fn applies_merge_keys() {
+        let values = parse_values("base: &base\n  a: 1\n  b: 2\nmerged:\n  <<: *base\n  b: 3\n").unwrap();
+        assert_eq!(values["merged"], serde_yaml::from_str::<Value>("{a: 1, b: 3}").unwrap());
+        assert!(values["merged"].get("<<").is_none());
+    }
+
+    #[test]
+    fn applies_merge_keys_in_order() {
+        let values = parse_values("one: &one {a: 1}\ntwo: &two {a: 2, b: 2}\nmerged:\n  <<: [*one, *two]\n").unwrap();
+        assert_eq!(values["merged"], serde_yaml::from_str::<Value>("{a: 1, b: 2}").unwrap());
+    }
+
+    #[test]
+    fn parses_only_the_first_document() {
+        assert_eq!(parse_values("a: 1\n---\nb: 2\n").unwrap(), serde_yaml::from_str::<Value>("a: 1").unwrap());
+        assert_eq!(parse_values("").unwrap(), Value::Null);
+    }
+
+    fn diff(original_text: &str, latest_text: &str) -> Vec<(String, ValueChange)> {
+        diff_values(&parse_values(original_text).unwrap(), &parse_values(latest_text).unwrap()).into_iter()
+            .map(|(key_path, value_change)| (format_key_path(&key_path), value_change))
+            .collect()
+    }
+
+    #[test]
+    fn diffs_added_removed_and_changed_keys() {
+        let changes = diff("replicas: 1\nold: true\nimage:\n  tag: \"1.0\"\n", "replicas: 2\nnew: true\nimage:\n  tag: \"1.0\"\n");
+        assert_eq!(changes, vec![
+            (String::from("replicas"), ValueChange::Changed(Value::from(1), Value::from(2))),
+            (String::from("old"), ValueChange::Removed(Value::from(true))),
+            (String::from("new"), ValueChange::Added(Value::from(true))),
+        ]);
+    }
+
+    #[test]
+    fn diffs_nested_maps() {
+        let changes = diff("image:\n  tag: \"1.0\"\n", "image:\n  tag: \"2.0\"\n  pullPolicy: Always\nservice:\n  port: 80\n  type: ClusterIP\n");
+        assert_eq!(changes, vec![
+            (String::from("image.tag"), ValueChange::Changed(Value::from("1.0"), Value::from("2.0"))),
+            (String::from("image.pullPolicy"), ValueChange::Added(Value::from("Always"))),
+            // A new map is one change, not one per key
+            (String::from("service"), ValueChange::Added(parse_values("port: 80\ntype: ClusterIP\n").unwrap())),
+        ]);
+        assert!(diff("a: {b: 1}\n", "a: {b: 1}\n").is_empty());
+    }
+
+    #[test]
+    fn quotes_keys_with_dots() {
+        assert_eq!(format_key_path(&key_path(&["image", "tag"])), "image.tag");
+        assert_eq!(format_key_path(&key_path(&["podAnnotations", "prometheus.io/scrape"])), r#"podAnnotations."prometheus.io/scrape""#);
+        assert_eq!(format_key_path(&key_path(&["a.\"b\""])), r#""a.\"b\"""#);
+    }
+
+    #[test]
+    fn matches_globs() {
+        assert!(key_path_matches("image.tag", &key_path(&["image", "tag"])));
+        assert!(!key_path_matches("image.tag", &key_path(&["image"])));
+        assert!(key_path_matches("*.apiVersion", &key_path(&["ingress", "apiVersion"])));
+        assert!(!key_path_matches("*.apiVersion", &key_path(&["apiVersion"])));
+        assert!(key_path_matches("image*.tag", &key_path(&["imageInit", "tag"])));
+        assert!(key_path_matches("resources.**", &key_path(&["resources"])));
+        assert!(key_path_matches("resources.**", &key_path(&["resources", "limits", "cpu"])));
+        assert!(key_path_matches("**.tag", &key_path(&["a", "b", "tag"])));
+    }
+
+    #[test]
+    fn matches_quoted_keys_literally() {
+        let scrape_key_path = key_path(&["podAnnotations", "prometheus.io/scrape"]);
+        assert!(key_path_matches(r#"podAnnotations."prometheus.io/scrape""#, &scrape_key_path));
+        assert!(key_path_matches(&format_key_path(&scrape_key_path), &scrape_key_path));
+        assert!(!key_path_matches("podAnnotations.prometheus.io/scrape", &scrape_key_path));
+        assert!(!key_path_matches(r#""*".tag"#, &key_path(&["image", "tag"])));
+        assert!(key_path_matches(r#""*".tag"#, &key_path(&["*", "tag"])));
+        assert!(key_path_matches(r#""a.\"b\"""#, &key_path(&["a.\"b\""])));
+    }
+
+    fn orphans(values_text: &str, original_text: &str, latest_text: &str) -> Vec<(String, Option<String>)> {
+        let parse = |values_text: &str| parse_values(values_text).unwrap();
+        find_orphaned_overrides(&parse(values_text), &parse(original_text), &parse(latest_text)).into_iter()
+            .map(|orphaned_override| (format_key_path(&orphaned_override.key_path), orphaned_override.suggestion))
+            .collect()
+    }
+
+    #[test]
+    fn finds_overrides_of_removed_keys() {
+        let original_text = "image:\n  tag: \"1.0\"\nlegacy:\n  enabled: false\n  port: 80\n";
+        let latest_text = "image:\n  tag: \"2.0\"\n";
+        assert_eq!(orphans("image:\n  tag: \"1.1\"\nlegacy:\n  enabled: true\n  port: 8080\n", original_text, latest_text),
+                   vec![(String::from("legacy"), None)]);
+        // Keys that are set to the original default aren't overrides
+        assert!(orphans("legacy:\n  enabled: false\n", original_text, latest_text).is_empty());
+    }
+
+    #[test]
+    fn suggests_the_key_a_removed_key_was_renamed_to() {
+        let original_text = "service:\n  port: 80\n  type: ClusterIP\n";
+        let latest_text = "service:\n  ports:\n    http: 80\n  type: ClusterIP\n  annotations: {}\n";
+        assert_eq!(orphans("service:\n  port: 8080\n", original_text, latest_text),
+                   vec![(String::from("service.port"), Some(String::from("service.ports")))]);
+        // Nothing close enough
+        let latest_text = "service:\n  listeners: []\n  type: ClusterIP\n";
+        assert_eq!(orphans("service:\n  port: 8080\n", original_text, latest_text), vec![(String::from("service.port"), None)]);
+    }
+
+    #[test]
+    fn doesnt_orphan_keys_of_free_form_maps_or_lists() {
+        let original_text = "resources: {}\nnodeSelector: {}\ntolerations: []\nextraEnv: []\n";
+        let latest_text = "resources: {}\nnodeSelector: {}\ntolerations: []\nextraEnv: []\n";
+        let values_text = concat!(
+            "resources:\n  limits:\n    cpu: 100m\n",
+            "nodeSelector:\n  disktype: ssd\n",
+            "tolerations:\n  - key: dedicated\n    operator: Exists\n",
+            "extraEnv:\n  - name: LOG_LEVEL\n    value: debug\n",
+        );
+        assert!(orphans(values_text, original_text, latest_text).is_empty());
+    }
+
+    #[test]
+    fn keeps_the_innermost_key_paths() {
+        let key_paths = vec![key_path(&["image"]), key_path(&["image", "tag"]), key_path(&["replicas"])];
+        assert_eq!(innermost_key_paths(key_paths), vec![key_path(&["image", "tag"]), key_path(&["replicas"])]);
+    }
+}
@@ -0,0 +1,326 @@
+use crate::values::KeyPath;
+
+// Helpers to work with values files as text, for the places where comments and formatting have to survive.
+// They understand block style YAML, which is what values files are written in, and treat anything else as opaque.
+
+// Splits `key: value` into the unquoted key and the rest of the line after the colon
+pub fn split_key_line(trimmed_line: &str) -> Option<(String, &str)> {
+    let (key, after_key) = match trimmed_line.chars().next()? {
+        quote @ ('"' | '\'') => {
+            let closing_quote = trimmed_line[1..].find(quote)? + 1;
+            (trimmed_line[1..closing_quote].to_string(), &trimmed_line[closing_quote + 1..])
+        }
+        '-' | '[' | '{' | '&' | '*' | '!' | '?' | '|' | '>' => return None,
+        _ => {
+            let colon = trimmed_line.match_indices(':')
+                .map(|(colon, _)| colon)
+                .find(|colon| trimmed_line[colon + 1..].is_empty() || trimmed_line[colon + 1..].starts_with(' '))?;
+            (trimmed_line[..colon].trim_end().to_string(), &trimmed_line[colon..])
+        }
+    };
+
+    let rest = after_key.trim_start().strip_prefix(':')?;
+    if !rest.is_empty() && !rest.starts_with(' ') {
+        return None;
+    }
+    Some((key, rest.trim()))
+}
+
+/// Whether `line` starts or ends a YAML document, like `---` or `...`.
+pub fn is_document_marker(line: &str) -> bool {
+    let line = line.trim_end();
+    ["---", "..."].iter().any(|marker| line.strip_prefix(marker).is_some_and(|rest| rest.is_empty() || rest.starts_with(' ')))
+}
+
+/// Returns the names of the anchors (`&name`) and of the aliases (`*name`) on `line`, outside of quotes and comments.
+pub fn anchors_and_aliases(line: &str) -> (Vec<&str>, Vec<&str>) {
+    let mut anchors = Vec::new();
+    let mut aliases = Vec::new();
+    let mut quote = None;
+    let mut previous_character = ' ';
+
+    for (position, character) in line.char_indices() {
+        let follows_separator = previous_character.is_whitespace() || ",[{-".contains(previous_character);
+        previous_character = character;
+        if let Some(opening_quote) = quote {
+            if character == opening_quote {
+                quote = None;
+            }
+            continue;
+        }
+
+        match character {
+            '"' | '\'' if follows_separator => quote = Some(character),
+            '#' if follows_separator => break,
+            '&' | '*' if follows_separator => {
+                let name_start = position + 1;
+                let name_len = line[name_start..].find(|c: char| c.is_whitespace() || ",[]{}".contains(c)).unwrap_or(line.len() - name_start);
+                let name = &line[name_start..name_start + name_len];
+                if !name.starts_with(|c: char| c.is_alphanumeric() || c == '_' || c == '-') {
+                    continue;
+                }
+                if character == '&' { anchors.push(name) } else { aliases.push(name) }
+            }
+            _ => {}
+        }
+    }
+
+    (anchors, aliases)
+}
+
+/// Splits a values file into its first YAML document and the documents after it, which helm ignores.
+pub fn split_first_document(values_text: &str) -> (&str, &str) {
+    let mut has_content = false;
+    let mut position = 0;
+    for line in values_text.split_inclusive('\n') {
+        if is_document_marker(line) {
+            if has_content || line.starts_with("...") {
+                return values_text.split_at(position);
+            }
+        } else if !line.trim().is_empty() && !line.trim_start().starts_with('#') {
+            has_content = true;
+        }
+        position += line.len();
+    }
+    (values_text, "")
+}
+
+/// Returns every anchor in the first document of a values file, with the key path of the value it names.
+pub fn find_anchors(values_text: &str) -> Vec<(String, KeyPath)> {
+    let mut anchors = Vec::new();
+    let mut tracker = KeyPathTracker::default();
+    for line in split_first_document(values_text).0.lines() {
+        if let Some(key_path) = tracker.track(line) {
+            anchors.extend(anchors_and_aliases(line).0.into_iter().map(|anchor| (String::from(anchor), key_path.clone())));
+        }
+    }
+    anchors
+}
+
+/// Whether the value after a key starts a block scalar, like `|`, `>-` or `&anchor |2`.
+pub fn starts_block_scalar(value: &str) -> bool {
+    value.split_whitespace()
+        .find(|token| !token.starts_with(['&', '!']))
+        .is_some_and(|token| token.starts_with(['|', '>']))
+}
+
+/// Follows the key paths through the lines of a values file, so every line can be attributed to a key.
+#[derive(Debug, Clone, Default)]
+pub struct KeyPathTracker {
+    keys: Vec<(usize, KeyPath)>,
+    // The indentation of the list being read and the key it belongs to, everything in a list belongs to its key
+    list: Option<(usize, KeyPath)>,
+    // The indentation of the key whose block scalar (`|` or `>`) is being read and its path, the lines of the text
+    // can look like keys or comments
+    block_scalar: Option<(usize, KeyPath)>,
+    // Whether the last tracked line was a key line
+    key_line: bool,
+}
+
+impl KeyPathTracker {
+    /// Returns the key path that `line` sets or belongs to, e.g. `image.tag` for the items of a list under `image.tag`.
+    pub fn track(&mut self, line: &str) -> Option<KeyPath> {
+        let line = line.trim_end_matches(['\n', '\r']);
+        let trimmed_line = line.trim_start();
+        let indent = line.len() - trimmed_line.len();
+        self.key_line = false;
+        if trimmed_line.is_empty() {
+            return None;
+        }
+        if let Some((block_scalar_indent, block_scalar_key_path)) = &self.block_scalar {
+            if indent > *block_scalar_indent {
+                return Some(block_scalar_key_path.clone());
+            }
+            self.block_scalar = None;
+        }
+        if trimmed_line.starts_with('#') {
+            return None;
+        }
+        // Every document starts from the top again
+        if is_document_marker(line) {
+            *self = KeyPathTracker::default();
+            return None;
+        }
+
+        if let Some((list_indent, list_key_path)) = &self.list {
+            if indent > *list_indent || (indent == *list_indent && trimmed_line.starts_with('-')) {
+                return Some(list_key_path.clone());
+            }
+            self.list = None;
+        }
+
+        match split_key_line(trimmed_line) {
+            Some((key, value)) => {
+                while self.keys.last().is_some_and(|(key_indent, _)| *key_indent >= indent) {
+                    self.keys.pop();
+                }
+                let mut key_path = self.keys.last().map(|(_, parent_key_path)| parent_key_path.clone()).unwrap_or_default();
+                key_path.push(key);
+                self.keys.push((indent, key_path.clone()));
+                if starts_block_scalar(value) {
+                    self.block_scalar = Some((indent, key_path.clone()));
+                }
+                self.key_line = true;
+                Some(key_path)
+            }
+            // Lists can be indented as much as the key they belong to
+            None => {
+                let key_path = self.keys.iter().rev()
+                    .find(|(key_indent, _)| *key_indent < indent || (*key_indent == indent && trimmed_line.starts_with('-')))
+                    .map(|(_, key_path)| key_path.clone());
+                if let (Some(key_path), true) = (&key_path, trimmed_line.starts_with('-')) {
+                    self.list = Some((indent, key_path.clone()));
+                }
+                key_path
+            }
+        }
+    }
+}
+
+/// The lines of a key in a values file: the comments right above it, the key line and everything under it.
+#[derive(Debug, Clone)]
+pub struct KeyBlock {
+    pub key_path: KeyPath,
+    pub indent: usize,
+    // The first comment line right above the key line, or the key line itself
+    pub start: usize,
+    pub key_line: usize,
+    // The line after the last line with content under the key, so trailing comments belong to the next key
+    pub end: usize,
+    pub blank_line_before: bool,
+}
+
+/// Finds the block of every key in the lines of a single YAML document, in the order of the lines.
+pub fn find_key_blocks(lines: &[&str]) -> Vec<KeyBlock> {
+    let mut tracker = KeyPathTracker::default();
+    let mut blocks: Vec<KeyBlock> = Vec::new();
+    // The blocks the current line can still belong to, innermost last
+    let mut open_blocks: Vec<usize> = Vec::new();
+
+    for (line_index, line) in lines.iter().enumerate() {
+        let key_path = match tracker.track(line) {
+            Some(key_path) => key_path,
+            None => continue,
+        };
+        let trimmed_line = line.trim_start();
+        let is_key_line = tracker.key_line;
+
+        while open_blocks.last().is_some_and(|&block_index| {
+            let block_key_path = &blocks[block_index].key_path;
+            !key_path.starts_with(block_key_path) || (is_key_line && key_path.len() == block_key_path.len())
+        }) {
+            open_blocks.pop();
+        }
+        for &block_index in &open_blocks {
+            blocks[block_index].end = line_index + 1;
+        }
+
+        if is_key_line {
+            let mut start = line_index;
+            while start > 0 && lines[start - 1].trim_start().starts_with('#') {
+                start -= 1;
+            }
+            open_blocks.push(blocks.len());
+            blocks.push(KeyBlock {
+                key_path,
+                indent: line.len() - trimmed_line.len(),
+                start,
+                key_line: line_index,
+                end: line_index + 1,
+                blank_line_before: start > 0 && lines[start - 1].trim().is_empty(),
+            });
+        }
+    }
+
+    blocks
+}
+
+/// Returns how many spaces nested keys are indented by in most places, 2 when nothing is nested.
+pub fn indent_unit(blocks: &[KeyBlock]) -> usize {
+    let mut indent_counts: Vec<(usize, usize)> = Vec::new();
+    for block in blocks {
+        let parent_key_path = &block.key_path[..block.key_path.len() - 1];
+        let parent_indent = match blocks.iter().rev().find(|parent| parent.key_line < block.key_line && parent.key_path == parent_key_path) {
+            Some(parent) => parent.indent,
+            None => continue,
+        };
+        let indent = match block.indent.checked_sub(parent_indent) {
+            Some(indent) if indent > 0 => indent,
+            _ => continue,
+        };
+        match indent_counts.iter_mut().find(|(counted_indent, _)| *counted_indent == indent) {
+            Some((_, count)) => *count += 1,
+            None => indent_counts.push((indent, 1)),
+        }
+    }
+    indent_counts.sort();
+    // The smallest one wins a tie
+    indent_counts.iter().rev().max_by_key(|(_, count)| *count).map_or(2, |(indent, _)| *indent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_anchors_and_aliases_outside_of_quotes_and_comments() {
+        assert_eq!(anchors_and_aliases("image: &image"), (vec!["image"], vec![]));
+        assert_eq!(anchors_and_aliases("  <<: *defaults"), (vec![], vec!["defaults"]));
+        assert_eq!(anchors_and_aliases("list: [*a, *b]"), (vec![], vec!["a", "b"]));
+        assert_eq!(anchors_and_aliases("- &item name"), (vec!["item"], vec![]));
+        assert_eq!(anchors_and_aliases("password: \"&not *this\" # &nor *that"), (vec![], vec![]));
+        assert_eq!(anchors_and_aliases("glob: a*b&c"), (vec![], vec![]));
+        assert_eq!(anchors_and_aliases("pattern: * "), (vec![], vec![]));
+    }
+
+    #[test]
+    fn splits_off_the_documents_after_the_first() {
+        assert_eq!(split_first_document("a: 1\n---\nb: 2\n"), ("a: 1\n", "---\nb: 2\n"));
+        // A leading marker starts the first document
+        assert_eq!(split_first_document("# values\n---\na: 1\n---\nb: 2\n"), ("# values\n---\na: 1\n", "---\nb: 2\n"));
+        assert_eq!(split_first_document("a: 1\n...\n"), ("a: 1\n", "...\n"));
+        assert_eq!(split_first_document("a: 1\nb: '---'\n"), ("a: 1\nb: '---'\n", ""));
+    }
+
+    #[test]
+    fn finds_the_blocks_of_the_keys() {
+        let lines = vec!["replicas: 1", "", "# the image", "image:", "  repository: nginx", "  # pinned", "  tag: \"1.19\"", "# trailing", "ports:", "  - 80", "  - 443"];
+        let blocks = find_key_blocks(&lines);
+        let summary: Vec<(String, usize, usize, usize, usize, bool)> = blocks.iter()
+            .map(|block| (block.key_path.join("."), block.indent, block.start, block.key_line, block.end, block.blank_line_before))
+            .collect();
+        assert_eq!(summary, vec![
+            (String::from("replicas"), 0, 0, 0, 1, false),
+            (String::from("image"), 0, 2, 3, 7, true),
+            (String::from("image.repository"), 2, 4, 4, 5, false),
+            (String::from("image.tag"), 2, 5, 6, 7, false),
+            (String::from("ports"), 0, 7, 8, 11, false),
+        ]);
+        assert_eq!(indent_unit(&blocks), 2);
+    }
+
+    #[test]
+    fn attributes_block_scalar_lines_to_their_key() {
+        let lines = ["config: |", "  foo: bar", "  # not a comment", "", "  baz: qux", "script: >-", "    run: this", "after: 1"];
+        let mut tracker = KeyPathTracker::default();
+        let key_paths: Vec<Option<String>> = lines.iter().map(|line| tracker.track(line).map(|key_path| key_path.join("."))).collect();
+        assert_eq!(key_paths, vec![
+            Some(String::from("config")), Some(String::from("config")), Some(String::from("config")), None, Some(String::from("config")),
+            Some(String::from("script")), Some(String::from("script")), Some(String::from("after")),
+        ]);
+
+        let block_key_paths: Vec<String> = find_key_blocks(&lines).iter().map(|block| block.key_path.join(".")).collect();
+        assert_eq!(block_key_paths, vec!["config", "script", "after"]);
+    }
+
+    #[test]
+    fn recognizes_block_scalars() {
+        assert!(starts_block_scalar("|"));
+        assert!(starts_block_scalar(">- # folded"));
+        assert!(starts_block_scalar("&script |2"));
+        assert!(starts_block_scalar("!!str |"));
+        assert!(!starts_block_scalar("a | b"));
+        assert!(!starts_block_scalar("\"|\""));
+        assert!(!starts_block_scalar(""));
+    }
+}